@@ -0,0 +1,27 @@
+use bantam_rs::arena::ExprArena;
+use bantam_rs::parser::BantamParser;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+// a long formula with calls, conditionals and every operator precedence
+fn source(terms: usize) -> String {
+    vec!["f(a, -b ^ c!, k: d) * (e - 1.5) / (h ? i : j)"; terms].join(" + ")
+}
+
+fn parse(c: &mut Criterion) {
+    let source = source(2_000);
+    let mut group = c.benchmark_group("parse");
+    group.bench_function("boxed", |b| {
+        b.iter(|| BantamParser::new(black_box(&source)).parse_expression())
+    });
+    group.bench_function("arena", |b| {
+        b.iter(|| {
+            let mut parser = BantamParser::with_builder(black_box(&source), ExprArena::default());
+            let root = parser.parse_expression();
+            (root, parser.into_builder())
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, parse);
+criterion_main!(benches);
@@ -0,0 +1,138 @@
+use crate::builder::{AstBuilder, Boxed, ExprNode};
+use crate::expression::Expr;
+use crate::token::Span;
+use std::borrow::Cow;
+use std::ops::Index;
+
+/// Index of a node in an `ExprArena`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ExprId(u32);
+
+/// All nodes of a parse in one `Vec`, children referring to each other by
+/// `ExprId`. Parsing into an arena takes a handful of reallocations instead
+/// of one allocation per node:
+///
+/// ```
+/// use bantam_rs::arena::ExprArena;
+/// use bantam_rs::parser::BantamParser;
+///
+/// let mut parser = BantamParser::with_builder("a + b * c", ExprArena::default());
+/// let root = parser.parse_expression();
+/// let arena = parser.into_builder();
+/// assert_eq!(arena.len(), 5);
+/// assert_eq!(arena.span(root).end, 9);
+/// ```
+///
+/// A parselet that takes a node apart, like a desugared pipe, leaves the old
+/// slot behind unused.
+#[derive(Debug, Default, Clone)]
+pub struct ExprArena<'source> {
+    nodes: Vec<ExprNode<'source, ExprId>>,
+}
+
+impl<'source> ExprArena<'source> {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            nodes: Vec::with_capacity(capacity),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn span(&self, id: ExprId) -> Span {
+        self[id].span()
+    }
+
+    /// Copy the subtree at `id` out into a boxed `Expr`, for the printers and
+    /// visitors that work on trees.
+    pub fn to_expr(&self, id: ExprId) -> Expr<'source> {
+        let node = self[id].clone().map(|child| Box::new(self.to_expr(child)));
+        *Boxed.build(node)
+    }
+}
+
+impl<'source> Index<ExprId> for ExprArena<'source> {
+    type Output = ExprNode<'source, ExprId>;
+
+    fn index(&self, id: ExprId) -> &Self::Output {
+        &self.nodes[id.0 as usize]
+    }
+}
+
+impl<'source> AstBuilder<'source> for ExprArena<'source> {
+    type Node = ExprId;
+
+    fn build(&mut self, node: ExprNode<'source, ExprId>) -> ExprId {
+        let id = ExprId(self.nodes.len().try_into().expect("Too many nodes for an ExprArena."));
+        self.nodes.push(node);
+        id
+    }
+
+    fn take(&mut self, id: ExprId) -> ExprNode<'source, ExprId> {
+        let unused = ExprNode::Name {
+            name: Cow::Borrowed(""),
+            span: Span::default(),
+        };
+        std::mem::replace(&mut self.nodes[id.0 as usize], unused)
+    }
+
+    fn span(&self, id: &ExprId) -> Span {
+        self[*id].span()
+    }
+
+    fn set_span(&mut self, id: &mut ExprId, span: Span) {
+        self.nodes[id.0 as usize].set_span(span);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::IF;
+    use crate::parser::BantamParser;
+    use crate::token::TokenType;
+
+    fn assert_same_tree(source: &str, desugar: bool) {
+        let mut boxed = BantamParser::new(source);
+        let mut arena = BantamParser::with_builder(source, ExprArena::default());
+        boxed.register_mixfix(&IF);
+        arena.register_mixfix(&IF);
+        if desugar {
+            boxed.desugar_pipes();
+            arena.desugar_pipes();
+        }
+        let expected = boxed.parse_expression();
+        let root = arena.parse_expression();
+        let arena = arena.into_builder();
+        let expr = arena.to_expr(root);
+        assert_eq!(expr.to_string(), expected.to_string());
+        assert_eq!(expr.span(), expected.span());
+    }
+
+    #[test]
+    fn same_tree_as_boxed() {
+        assert_same_tree("a = b + c * d ^ e - f / g", false);
+        assert_same_tree("f(a, ...b, k: c ? -d! : (e))", false);
+        assert_same_tree("if a then b |> f(c) else d", false);
+        assert_same_tree("a |> f(b) |> g", true);
+    }
+
+    #[test]
+    fn nodes_refer_by_id() {
+        let mut parser = BantamParser::with_builder("-a", ExprArena::default());
+        let root = parser.parse_expression();
+        let arena = parser.into_builder();
+        let ExprNode::Prefix { op, right, span } = &arena[root] else {
+            panic!("expected a prefix node");
+        };
+        assert_eq!(*op, TokenType::Minus);
+        assert_eq!(*span, Span::new(0, 2));
+        assert!(matches!(&arena[*right], ExprNode::Name { name, .. } if name == "a"));
+    }
+}
@@ -0,0 +1,213 @@
+use crate::expression::{Arg, Expr};
+
+/// A pair of subtrees that differ between two versions of an expression.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Difference<'a, 'source> {
+    /// Where the subtrees sit below the roots, like `right.args[1]`, empty
+    /// for the roots themselves.
+    pub path: String,
+    pub old: &'a Expr<'source>,
+    pub new: &'a Expr<'source>,
+}
+
+/// The smallest subtrees in which `old` and `new` differ, left to right.
+///
+/// Two nodes with the same operator, name or pattern and the same number
+/// and kinds of operands are compared operand by operand; otherwise they
+/// are reported as a whole. Spans are ignored, so moving a formula around
+/// in a file doesn't change it.
+pub fn ast_diff<'a, 'source>(
+    old: &'a Expr<'source>,
+    new: &'a Expr<'source>,
+) -> Vec<Difference<'a, 'source>> {
+    let mut differences = vec![];
+    diff(String::new(), old, new, &mut differences);
+    differences
+}
+
+fn diff<'a, 'source>(
+    path: String,
+    old: &'a Expr<'source>,
+    new: &'a Expr<'source>,
+    out: &mut Vec<Difference<'a, 'source>>,
+) {
+    if old.eq_ignore_spans(new) {
+        return;
+    }
+    let child = |name: &str| {
+        if path.is_empty() {
+            name.to_string()
+        } else {
+            format!("{path}.{name}")
+        }
+    };
+    match (old, new) {
+        (
+            Expr::Assign { name, right, .. },
+            Expr::Assign {
+                name: new_name,
+                right: new_right,
+                ..
+            },
+        ) if name == new_name => diff(child("right"), right, new_right, out),
+        (
+            Expr::FnDef {
+                name, params, body, ..
+            },
+            Expr::FnDef {
+                name: new_name,
+                params: new_params,
+                body: new_body,
+                ..
+            },
+        ) if name == new_name && params == new_params => diff(child("body"), body, new_body, out),
+        (
+            Expr::Lambda { param, body, .. },
+            Expr::Lambda {
+                param: new_param,
+                body: new_body,
+                ..
+            },
+        ) if param == new_param => diff(child("body"), body, new_body, out),
+        (
+            Expr::Call { func, args, .. },
+            Expr::Call {
+                func: new_func,
+                args: new_args,
+                ..
+            },
+        ) if same_kinds(args, new_args) => {
+            diff(child("func"), func, new_func, out);
+            for (i, (arg, new_arg)) in args.iter().zip(new_args).enumerate() {
+                diff(child(&format!("args[{i}]")), arg.value(), new_arg.value(), out);
+            }
+        }
+        (
+            Expr::Cond {
+                cond,
+                then_arm,
+                else_arm,
+                ..
+            },
+            Expr::Cond {
+                cond: new_cond,
+                then_arm: new_then,
+                else_arm: new_else,
+                ..
+            },
+        ) => {
+            diff(child("cond"), cond, new_cond, out);
+            diff(child("then_arm"), then_arm, new_then, out);
+            diff(child("else_arm"), else_arm, new_else, out);
+        }
+        (
+            Expr::Prefix { op, right, .. },
+            Expr::Prefix {
+                op: new_op,
+                right: new_right,
+                ..
+            },
+        ) if op == new_op => diff(child("right"), right, new_right, out),
+        (
+            Expr::Postfix { left, op, .. },
+            Expr::Postfix {
+                left: new_left,
+                op: new_op,
+                ..
+            },
+        ) if op == new_op => diff(child("left"), left, new_left, out),
+        (
+            Expr::Infix { left, op, right, .. },
+            Expr::Infix {
+                left: new_left,
+                op: new_op,
+                right: new_right,
+                ..
+            },
+        ) if op == new_op => {
+            diff(child("left"), left, new_left, out);
+            diff(child("right"), right, new_right, out);
+        }
+        (
+            Expr::Pipe { left, right, .. },
+            Expr::Pipe {
+                left: new_left,
+                right: new_right,
+                ..
+            },
+        ) => {
+            diff(child("left"), left, new_left, out);
+            diff(child("right"), right, new_right, out);
+        }
+        (
+            Expr::Mixfix {
+                pattern, operands, ..
+            },
+            Expr::Mixfix {
+                pattern: new_pattern,
+                operands: new_operands,
+                ..
+            },
+        ) if pattern == new_pattern && operands.len() == new_operands.len() => {
+            for (i, (operand, new_operand)) in operands.iter().zip(new_operands).enumerate() {
+                diff(child(&format!("operands[{i}]")), operand, new_operand, out);
+            }
+        }
+        _ => out.push(Difference { path, old, new }),
+    }
+}
+
+// positional, spread and named arguments in the same places, with the same names
+fn same_kinds(args: &[Arg], new_args: &[Arg]) -> bool {
+    args.len() == new_args.len()
+        && args.iter().zip(new_args).all(|(arg, new_arg)| match (arg, new_arg) {
+            (Arg::Positional(_), Arg::Positional(_)) | (Arg::Spread(_), Arg::Spread(_)) => true,
+            (Arg::Named { name, .. }, Arg::Named { name: new_name, .. }) => name == new_name,
+            _ => false,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::BantamParser;
+
+    // each difference as `path: old -> new`
+    fn changes(old: &str, new: &str) -> Vec<String> {
+        let old = BantamParser::new(old).parse_expression();
+        let new = BantamParser::new(new).parse_expression();
+        ast_diff(&old, &new)
+            .iter()
+            .map(|d| format!("{}: {} -> {}", d.path, d.old, d.new))
+            .collect()
+    }
+
+    #[test]
+    fn smallest_differing_subtrees() {
+        assert_eq!(
+            changes("a = f(b, c) + d * e", "a = f(b, x) + d / e"),
+            ["right.left.args[1]: c -> x", "right.right: (d * e) -> (d / e)"]
+        );
+        assert_eq!(
+            changes("c ? g(k: 1) : -e!", "c ? g(k: 2) : -f!"),
+            ["then_arm.args[0]: 1 -> 2", "else_arm.right.left: e -> f"]
+        );
+    }
+
+    #[test]
+    fn mismatched_nodes_differ_as_a_whole() {
+        assert_eq!(changes("a = b", "x = b"), [": (a = b) -> (x = b)"]);
+        assert_eq!(changes("f(a)", "f(a, b)"), [": f(a) -> f(a, b)"]);
+        assert_eq!(changes("f(k: a)", "f(j: a)"), [": f(k: a) -> f(j: a)"]);
+        assert_eq!(changes("a |> f", "-a"), [": (a |> f) -> (-a)"]);
+    }
+
+    #[test]
+    fn spans_are_ignored() {
+        assert!(changes("a+b*c", "  a + b * c").is_empty());
+        let old = BantamParser::new("a+b").parse_expression();
+        let new = BantamParser::new(" a+b").parse_expression();
+        assert_ne!(old, new);
+        assert!(old.eq_ignore_spans(&new));
+    }
+}
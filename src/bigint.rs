@@ -0,0 +1,413 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, Mul, Neg, Sub};
+
+/// An integer of any size: a sign and the magnitude in base 2^32, least
+/// significant limb first, without leading zero limbs. Zero has no limbs
+/// and is never negative, so equal numbers are equal values.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct BigInt {
+    negative: bool,
+    mag: Vec<u32>,
+}
+
+impl BigInt {
+    fn from_parts(negative: bool, mut mag: Vec<u32>) -> Self {
+        while mag.last() == Some(&0) {
+            mag.pop();
+        }
+        Self {
+            negative: negative && !mag.is_empty(),
+            mag,
+        }
+    }
+
+    /// Parse decimal digits with an optional leading `-`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, s),
+        };
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let mut mag = vec![];
+        // nine digits at a time, the most that fit a limb
+        let head = digits.len() % 9;
+        let chunks = std::iter::once(&digits[..head])
+            .filter(|chunk| !chunk.is_empty())
+            .chain(
+                digits.as_bytes()[head..]
+                    .chunks(9)
+                    .map(|c| std::str::from_utf8(c).unwrap()),
+            );
+        for chunk in chunks {
+            let scale = 10u32.pow(chunk.len() as u32);
+            mul_small_add(&mut mag, scale, chunk.parse().unwrap());
+        }
+        Some(Self::from_parts(negative, mag))
+    }
+
+    /// The integer `x` if it is finite and has no fraction.
+    pub fn from_f64(x: f64) -> Option<Self> {
+        if !x.is_finite() || x.fract() != 0.0 {
+            return None;
+        }
+        // x = mantissa * 2^exp with a 53-bit mantissa
+        let bits = x.abs().to_bits();
+        let exp = ((bits >> 52) & 0x7ff) as i64;
+        if exp == 0 {
+            // zero, as subnormals all have a fraction
+            return Some(Self::default());
+        }
+        let mantissa = (bits & ((1 << 52) - 1)) | (1 << 52);
+        let shift = exp - 1075;
+        let n = if shift >= 0 {
+            &BigInt::from(mantissa as i64) * &BigInt::from(2).pow(shift as u64)
+        } else {
+            BigInt::from((mantissa >> -shift) as i64)
+        };
+        Some(if x < 0.0 { -&n } else { n })
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.mag.is_empty()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    /// The number of bits of the magnitude.
+    pub fn bits(&self) -> u64 {
+        match self.mag.last() {
+            Some(top) => self.mag.len() as u64 * 32 - u64::from(top.leading_zeros()),
+            None => 0,
+        }
+    }
+
+    pub fn to_i64(&self) -> Option<i64> {
+        if self.mag.len() > 2 {
+            return None;
+        }
+        let mag = self
+            .mag
+            .iter()
+            .rev()
+            .fold(0u64, |acc, &limb| acc << 32 | u64::from(limb));
+        if self.negative {
+            0i64.checked_sub_unsigned(mag)
+        } else {
+            i64::try_from(mag).ok()
+        }
+    }
+
+    /// The nearest `f64`, infinite if it is too large.
+    pub fn to_f64(&self) -> f64 {
+        let mag = self
+            .mag
+            .iter()
+            .rev()
+            .fold(0.0, |acc, &limb| acc * 4294967296.0 + f64::from(limb));
+        if self.negative {
+            -mag
+        } else {
+            mag
+        }
+    }
+
+    /// The quotient rounded toward zero and the remainder, which has the sign
+    /// of `self`, like `/` and `%` on `i64`. `None` for a zero divisor.
+    pub fn div_rem(&self, divisor: &BigInt) -> Option<(BigInt, BigInt)> {
+        if divisor.is_zero() {
+            return None;
+        }
+        let (quotient, remainder) = div_rem_mag(&self.mag, &divisor.mag);
+        Some((
+            Self::from_parts(self.negative != divisor.negative, quotient),
+            Self::from_parts(self.negative, remainder),
+        ))
+    }
+
+    pub fn pow(&self, mut exp: u64) -> BigInt {
+        let mut result = BigInt::from(1);
+        let mut base = self.clone();
+        while exp > 0 {
+            if exp & 1 == 1 {
+                result = &result * &base;
+            }
+            exp >>= 1;
+            if exp > 0 {
+                base = &base * &base;
+            }
+        }
+        result
+    }
+
+    /// `self * factor`, cheaper than multiplying by a `BigInt`.
+    pub fn mul_small(&self, factor: u32) -> BigInt {
+        let mut mag = self.mag.clone();
+        mul_small_add(&mut mag, factor, 0);
+        Self::from_parts(self.negative, mag)
+    }
+}
+
+impl From<i64> for BigInt {
+    fn from(n: i64) -> Self {
+        let mag = n.unsigned_abs();
+        Self::from_parts(n < 0, vec![mag as u32, (mag >> 32) as u32])
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_zero() {
+            return f.write_str("0");
+        }
+        let mut mag = self.mag.clone();
+        let mut chunks = vec![];
+        while !mag.is_empty() {
+            chunks.push(div_small(&mut mag, 1_000_000_000));
+        }
+        if self.negative {
+            f.write_str("-")?;
+        }
+        let mut chunks = chunks.iter().rev();
+        write!(f, "{}", chunks.next().unwrap())?;
+        for chunk in chunks {
+            write!(f, "{chunk:09}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => cmp_mag(&self.mag, &other.mag),
+            (true, true) => cmp_mag(&other.mag, &self.mag),
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Neg for &BigInt {
+    type Output = BigInt;
+
+    fn neg(self) -> BigInt {
+        BigInt::from_parts(!self.negative, self.mag.clone())
+    }
+}
+
+impl Add for &BigInt {
+    type Output = BigInt;
+
+    fn add(self, other: &BigInt) -> BigInt {
+        if self.negative == other.negative {
+            return BigInt::from_parts(self.negative, add_mag(&self.mag, &other.mag));
+        }
+        match cmp_mag(&self.mag, &other.mag) {
+            Ordering::Less => BigInt::from_parts(other.negative, sub_mag(&other.mag, &self.mag)),
+            _ => BigInt::from_parts(self.negative, sub_mag(&self.mag, &other.mag)),
+        }
+    }
+}
+
+impl Sub for &BigInt {
+    type Output = BigInt;
+
+    fn sub(self, other: &BigInt) -> BigInt {
+        self + &-other
+    }
+}
+
+impl Mul for &BigInt {
+    type Output = BigInt;
+
+    fn mul(self, other: &BigInt) -> BigInt {
+        let mut mag = vec![0u32; self.mag.len() + other.mag.len()];
+        for (i, &a) in self.mag.iter().enumerate() {
+            let mut carry = 0u64;
+            for (j, &b) in other.mag.iter().enumerate() {
+                let t = u64::from(a) * u64::from(b) + u64::from(mag[i + j]) + carry;
+                mag[i + j] = t as u32;
+                carry = t >> 32;
+            }
+            mag[i + other.mag.len()] = carry as u32;
+        }
+        BigInt::from_parts(self.negative != other.negative, mag)
+    }
+}
+
+fn cmp_mag(a: &[u32], b: &[u32]) -> Ordering {
+    a.len()
+        .cmp(&b.len())
+        .then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let (long, short) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+    let mut sum = Vec::with_capacity(long.len() + 1);
+    let mut carry = 0u64;
+    for (i, &limb) in long.iter().enumerate() {
+        let t = u64::from(limb) + u64::from(short.get(i).copied().unwrap_or(0)) + carry;
+        sum.push(t as u32);
+        carry = t >> 32;
+    }
+    sum.push(carry as u32);
+    sum
+}
+
+// `a - b` for `a >= b`
+fn sub_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut difference = Vec::with_capacity(a.len());
+    let mut borrow = 0i64;
+    for (i, &limb) in a.iter().enumerate() {
+        let mut t = i64::from(limb) - i64::from(b.get(i).copied().unwrap_or(0)) - borrow;
+        borrow = 0;
+        if t < 0 {
+            t += 1 << 32;
+            borrow = 1;
+        }
+        difference.push(t as u32);
+    }
+    difference
+}
+
+// `mag * factor + add` in place
+fn mul_small_add(mag: &mut Vec<u32>, factor: u32, add: u32) {
+    let mut carry = u64::from(add);
+    for limb in mag.iter_mut() {
+        let t = u64::from(*limb) * u64::from(factor) + carry;
+        *limb = t as u32;
+        carry = t >> 32;
+    }
+    if carry > 0 {
+        mag.push(carry as u32);
+    }
+}
+
+// divide in place, returning the remainder
+fn div_small(mag: &mut Vec<u32>, divisor: u32) -> u32 {
+    let mut remainder = 0u64;
+    for limb in mag.iter_mut().rev() {
+        let t = remainder << 32 | u64::from(*limb);
+        *limb = (t / u64::from(divisor)) as u32;
+        remainder = t % u64::from(divisor);
+    }
+    while mag.last() == Some(&0) {
+        mag.pop();
+    }
+    remainder as u32
+}
+
+// shift-and-subtract long division, one bit of the quotient at a time
+fn div_rem_mag(a: &[u32], b: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    if cmp_mag(a, b) == Ordering::Less {
+        return (vec![], a.to_vec());
+    }
+    if let [divisor] = b {
+        let mut quotient = a.to_vec();
+        let remainder = div_small(&mut quotient, *divisor);
+        return (quotient, vec![remainder]);
+    }
+    let mut quotient = vec![0u32; a.len()];
+    let mut remainder: Vec<u32> = vec![];
+    for bit in (0..a.len() * 32).rev() {
+        // remainder = remainder * 2 + the next bit of `a`
+        let mut carry = (a[bit / 32] >> (bit % 32)) & 1;
+        for limb in remainder.iter_mut() {
+            let next = *limb >> 31;
+            *limb = *limb << 1 | carry;
+            carry = next;
+        }
+        if carry > 0 {
+            remainder.push(carry);
+        }
+        if cmp_mag(&remainder, b) != Ordering::Less {
+            remainder = sub_mag(&remainder, b);
+            while remainder.last() == Some(&0) {
+                remainder.pop();
+            }
+            quotient[bit / 32] |= 1 << (bit % 32);
+        }
+    }
+    (quotient, remainder)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn big(s: &str) -> BigInt {
+        BigInt::parse(s).unwrap()
+    }
+
+    #[test]
+    fn parse_and_display() {
+        for s in [
+            "0",
+            "7",
+            "-42",
+            "4294967296",
+            "-123456789012345678901234567890",
+        ] {
+            assert_eq!(big(s).to_string(), s);
+        }
+        assert_eq!(big("-0"), BigInt::default());
+        assert_eq!(big("000012").to_string(), "12");
+        assert_eq!(BigInt::parse("1x"), None);
+        assert_eq!(BigInt::parse("-"), None);
+    }
+
+    #[test]
+    fn i64_round_trip() {
+        for n in [0, 1, -1, i64::MAX, i64::MIN, 1 << 40] {
+            assert_eq!(BigInt::from(n).to_i64(), Some(n));
+            assert_eq!(BigInt::from(n).to_string(), n.to_string());
+        }
+        assert_eq!((&BigInt::from(i64::MAX) + &BigInt::from(1)).to_i64(), None);
+        assert_eq!(big("18446744073709551616").to_f64(), 18446744073709551616.0);
+        assert_eq!(BigInt::from_f64(-1e20), Some(big("-100000000000000000000")));
+        assert_eq!(BigInt::from_f64(3.0), Some(BigInt::from(3)));
+        assert_eq!(BigInt::from_f64(0.5), None);
+        assert_eq!(BigInt::from_f64(f64::INFINITY), None);
+    }
+
+    #[test]
+    fn arithmetic() {
+        let a = big("123456789012345678901234567890");
+        let b = big("-987654321098765432109876543210");
+        assert_eq!((&a + &b).to_string(), "-864197532086419753208641975320");
+        assert_eq!((&a - &b).to_string(), "1111111110111111111011111111100");
+        assert_eq!(
+            (&a * &b).to_string(),
+            "-121932631137021795226185032733622923332237463801111263526900"
+        );
+        assert_eq!(&a - &a, BigInt::default());
+        assert_eq!(
+            BigInt::from(2).pow(100).to_string(),
+            "1267650600228229401496703205376"
+        );
+        assert!(b < a && -&a < a && BigInt::from(-3) < BigInt::from(-2));
+    }
+
+    #[test]
+    fn division_truncates() {
+        let (q, r) = big("1267650600228229401496703205377")
+            .div_rem(&big("-4294967297"))
+            .unwrap();
+        assert_eq!(q.to_string(), "-295147905110633349135");
+        assert_eq!(r.to_string(), "4294967282");
+        let (q, r) = BigInt::from(-7).div_rem(&BigInt::from(2)).unwrap();
+        assert_eq!((q.to_i64(), r.to_i64()), (Some(-3), Some(-1)));
+        assert_eq!(BigInt::from(1).div_rem(&BigInt::default()), None);
+    }
+}
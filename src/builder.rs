@@ -0,0 +1,460 @@
+use crate::expression::{Arg, Expr};
+use crate::token::{Span, TokenType};
+use std::borrow::Cow;
+
+/// One level of an expression whose children are `C`: the boxes of an
+/// `Expr` tree, or the ids of an `ExprArena`. Parselets only build and take
+/// apart nodes of this shape, so the same grammar produces either
+/// representation.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ExprNode<'source, C> {
+    Name {
+        name: Cow<'source, str>,
+        span: Span,
+    },
+    Number {
+        literal: Cow<'source, str>,
+        span: Span,
+    },
+    Assign {
+        name: Cow<'source, str>,
+        right: C,
+        span: Span,
+    },
+    FnDef {
+        name: Cow<'source, str>,
+        params: Vec<Cow<'source, str>>,
+        body: C,
+        span: Span,
+    },
+    Lambda {
+        param: Cow<'source, str>,
+        body: C,
+        span: Span,
+    },
+    Call {
+        func: C,
+        args: Vec<Arg<'source, C>>,
+        span: Span,
+    },
+    Cond {
+        cond: C,
+        then_arm: C,
+        else_arm: C,
+        span: Span,
+    },
+    Prefix {
+        op: TokenType,
+        right: C,
+        span: Span,
+    },
+    Postfix {
+        left: C,
+        op: TokenType,
+        span: Span,
+    },
+    Infix {
+        left: C,
+        op: TokenType,
+        right: C,
+        span: Span,
+    },
+    Pipe {
+        left: C,
+        right: C,
+        span: Span,
+    },
+    Mixfix {
+        pattern: Cow<'source, str>,
+        operands: Vec<C>,
+        span: Span,
+    },
+}
+
+/// Where a `Parser` puts the nodes its parselets build. `Node` is the handle
+/// parselets pass around: a `Box<Expr>` with `Boxed`, an `ExprId` with an
+/// `ExprArena`.
+pub trait AstBuilder<'source> {
+    type Node;
+
+    fn build(&mut self, node: ExprNode<'source, Self::Node>) -> Self::Node;
+
+    /// Take `node` apart one level, for parselets that rewrite what they were
+    /// handed, like `Assign` checking for a name on its left.
+    fn take(&mut self, node: Self::Node) -> ExprNode<'source, Self::Node>;
+
+    fn span(&self, node: &Self::Node) -> Span;
+
+    fn set_span(&mut self, node: &mut Self::Node, span: Span);
+}
+
+/// Builds the `Box<Expr>` tree, one allocation per node.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Boxed;
+
+impl<'source> AstBuilder<'source> for Boxed {
+    type Node = Box<Expr<'source>>;
+
+    fn build(&mut self, node: ExprNode<'source, Self::Node>) -> Self::Node {
+        Box::new(match node {
+            ExprNode::Name { name, span } => Expr::Name { name, span },
+            ExprNode::Number { literal, span } => Expr::Number { literal, span },
+            ExprNode::Assign { name, right, span } => Expr::Assign { name, right, span },
+            ExprNode::FnDef {
+                name,
+                params,
+                body,
+                span,
+            } => Expr::FnDef {
+                name,
+                params,
+                body,
+                span,
+            },
+            ExprNode::Lambda { param, body, span } => Expr::Lambda { param, body, span },
+            ExprNode::Call { func, args, span } => Expr::Call {
+                func,
+                args: args.into_iter().map(|arg| arg.map(|value| *value)).collect(),
+                span,
+            },
+            ExprNode::Cond {
+                cond,
+                then_arm,
+                else_arm,
+                span,
+            } => Expr::Cond {
+                cond,
+                then_arm,
+                else_arm,
+                span,
+            },
+            ExprNode::Prefix { op, right, span } => Expr::Prefix { op, right, span },
+            ExprNode::Postfix { left, op, span } => Expr::Postfix { left, op, span },
+            ExprNode::Infix {
+                left,
+                op,
+                right,
+                span,
+            } => Expr::Infix {
+                left,
+                op,
+                right,
+                span,
+            },
+            ExprNode::Pipe { left, right, span } => Expr::Pipe { left, right, span },
+            ExprNode::Mixfix {
+                pattern,
+                operands,
+                span,
+            } => Expr::Mixfix {
+                pattern,
+                operands: operands.into_iter().map(|operand| *operand).collect(),
+                span,
+            },
+        })
+    }
+
+    fn take(&mut self, node: Self::Node) -> ExprNode<'source, Self::Node> {
+        match *node {
+            Expr::Name { name, span } => ExprNode::Name { name, span },
+            Expr::Number { literal, span } => ExprNode::Number { literal, span },
+            Expr::Assign { name, right, span } => ExprNode::Assign { name, right, span },
+            Expr::FnDef {
+                name,
+                params,
+                body,
+                span,
+            } => ExprNode::FnDef {
+                name,
+                params,
+                body,
+                span,
+            },
+            Expr::Lambda { param, body, span } => ExprNode::Lambda { param, body, span },
+            Expr::Call { func, args, span } => ExprNode::Call {
+                func,
+                args: args.into_iter().map(|arg| arg.map(Box::new)).collect(),
+                span,
+            },
+            Expr::Cond {
+                cond,
+                then_arm,
+                else_arm,
+                span,
+            } => ExprNode::Cond {
+                cond,
+                then_arm,
+                else_arm,
+                span,
+            },
+            Expr::Prefix { op, right, span } => ExprNode::Prefix { op, right, span },
+            Expr::Postfix { left, op, span } => ExprNode::Postfix { left, op, span },
+            Expr::Infix {
+                left,
+                op,
+                right,
+                span,
+            } => ExprNode::Infix {
+                left,
+                op,
+                right,
+                span,
+            },
+            Expr::Pipe { left, right, span } => ExprNode::Pipe { left, right, span },
+            Expr::Mixfix {
+                pattern,
+                operands,
+                span,
+            } => ExprNode::Mixfix {
+                pattern,
+                operands: operands.into_iter().map(Box::new).collect(),
+                span,
+            },
+        }
+    }
+
+    fn span(&self, node: &Self::Node) -> Span {
+        node.span()
+    }
+
+    fn set_span(&mut self, node: &mut Self::Node, span: Span) {
+        node.set_span(span);
+    }
+}
+
+impl<'source, C> ExprNode<'source, C> {
+    pub fn span(&self) -> Span {
+        match self {
+            ExprNode::Name { span, .. }
+            | ExprNode::Number { span, .. }
+            | ExprNode::Assign { span, .. }
+            | ExprNode::FnDef { span, .. }
+            | ExprNode::Lambda { span, .. }
+            | ExprNode::Call { span, .. }
+            | ExprNode::Cond { span, .. }
+            | ExprNode::Prefix { span, .. }
+            | ExprNode::Postfix { span, .. }
+            | ExprNode::Infix { span, .. }
+            | ExprNode::Pipe { span, .. }
+            | ExprNode::Mixfix { span, .. } => *span,
+        }
+    }
+
+    pub fn set_span(&mut self, to: Span) {
+        match self {
+            ExprNode::Name { span, .. }
+            | ExprNode::Number { span, .. }
+            | ExprNode::Assign { span, .. }
+            | ExprNode::FnDef { span, .. }
+            | ExprNode::Lambda { span, .. }
+            | ExprNode::Call { span, .. }
+            | ExprNode::Cond { span, .. }
+            | ExprNode::Prefix { span, .. }
+            | ExprNode::Postfix { span, .. }
+            | ExprNode::Infix { span, .. }
+            | ExprNode::Pipe { span, .. }
+            | ExprNode::Mixfix { span, .. } => *span = to,
+        }
+    }
+
+    /// The children, left to right.
+    pub fn children(&self) -> Vec<&C> {
+        match self {
+            ExprNode::Name { .. } | ExprNode::Number { .. } => vec![],
+            ExprNode::Assign { right, .. } | ExprNode::Prefix { right, .. } => vec![right],
+            ExprNode::FnDef { body, .. } | ExprNode::Lambda { body, .. } => vec![body],
+            ExprNode::Postfix { left, .. } => vec![left],
+            ExprNode::Call { func, args, .. } => {
+                std::iter::once(func).chain(args.iter().map(Arg::value)).collect()
+            }
+            ExprNode::Cond {
+                cond,
+                then_arm,
+                else_arm,
+                ..
+            } => vec![cond, then_arm, else_arm],
+            ExprNode::Infix { left, right, .. } | ExprNode::Pipe { left, right, .. } => {
+                vec![left, right]
+            }
+            ExprNode::Mixfix { operands, .. } => operands.iter().collect(),
+        }
+    }
+
+    /// Replace every child by `f(child)`, calling `f` left to right.
+    pub fn map<D>(self, mut f: impl FnMut(C) -> D) -> ExprNode<'source, D> {
+        match self {
+            ExprNode::Name { name, span } => ExprNode::Name { name, span },
+            ExprNode::Number { literal, span } => ExprNode::Number { literal, span },
+            ExprNode::Assign { name, right, span } => ExprNode::Assign {
+                name,
+                right: f(right),
+                span,
+            },
+            ExprNode::FnDef {
+                name,
+                params,
+                body,
+                span,
+            } => ExprNode::FnDef {
+                name,
+                params,
+                body: f(body),
+                span,
+            },
+            ExprNode::Lambda { param, body, span } => ExprNode::Lambda {
+                param,
+                body: f(body),
+                span,
+            },
+            ExprNode::Call { func, args, span } => ExprNode::Call {
+                func: f(func),
+                args: args.into_iter().map(|arg| arg.map(&mut f)).collect(),
+                span,
+            },
+            ExprNode::Cond {
+                cond,
+                then_arm,
+                else_arm,
+                span,
+            } => ExprNode::Cond {
+                cond: f(cond),
+                then_arm: f(then_arm),
+                else_arm: f(else_arm),
+                span,
+            },
+            ExprNode::Prefix { op, right, span } => ExprNode::Prefix {
+                op,
+                right: f(right),
+                span,
+            },
+            ExprNode::Postfix { left, op, span } => ExprNode::Postfix {
+                left: f(left),
+                op,
+                span,
+            },
+            ExprNode::Infix {
+                left,
+                op,
+                right,
+                span,
+            } => ExprNode::Infix {
+                left: f(left),
+                op,
+                right: f(right),
+                span,
+            },
+            ExprNode::Pipe { left, right, span } => ExprNode::Pipe {
+                left: f(left),
+                right: f(right),
+                span,
+            },
+            ExprNode::Mixfix {
+                pattern,
+                operands,
+                span,
+            } => ExprNode::Mixfix {
+                pattern,
+                operands: operands.into_iter().map(f).collect(),
+                span,
+            },
+        }
+    }
+}
+
+impl<'a, 'source> ExprNode<'source, &'a Expr<'source>> {
+    /// The top level of `expr`, with its children borrowed.
+    pub fn view(expr: &'a Expr<'source>) -> Self {
+        match expr {
+            Expr::Name { name, span } => ExprNode::Name {
+                name: name.clone(),
+                span: *span,
+            },
+            Expr::Number { literal, span } => ExprNode::Number {
+                literal: literal.clone(),
+                span: *span,
+            },
+            Expr::Assign { name, right, span } => ExprNode::Assign {
+                name: name.clone(),
+                right,
+                span: *span,
+            },
+            Expr::FnDef {
+                name,
+                params,
+                body,
+                span,
+            } => ExprNode::FnDef {
+                name: name.clone(),
+                params: params.clone(),
+                body,
+                span: *span,
+            },
+            Expr::Lambda { param, body, span } => ExprNode::Lambda {
+                param: param.clone(),
+                body,
+                span: *span,
+            },
+            Expr::Call { func, args, span } => ExprNode::Call {
+                func,
+                args: args
+                    .iter()
+                    .map(|arg| match arg {
+                        Arg::Positional(value) => Arg::Positional(value),
+                        Arg::Named { name, value } => Arg::Named {
+                            name: name.clone(),
+                            value,
+                        },
+                        Arg::Spread(value) => Arg::Spread(value),
+                    })
+                    .collect(),
+                span: *span,
+            },
+            Expr::Cond {
+                cond,
+                then_arm,
+                else_arm,
+                span,
+            } => ExprNode::Cond {
+                cond,
+                then_arm,
+                else_arm,
+                span: *span,
+            },
+            Expr::Prefix { op, right, span } => ExprNode::Prefix {
+                op: *op,
+                right,
+                span: *span,
+            },
+            Expr::Postfix { left, op, span } => ExprNode::Postfix {
+                left,
+                op: *op,
+                span: *span,
+            },
+            Expr::Infix {
+                left,
+                op,
+                right,
+                span,
+            } => ExprNode::Infix {
+                left,
+                op: *op,
+                right,
+                span: *span,
+            },
+            Expr::Pipe { left, right, span } => ExprNode::Pipe {
+                left,
+                right,
+                span: *span,
+            },
+            Expr::Mixfix {
+                pattern,
+                operands,
+                span,
+            } => ExprNode::Mixfix {
+                pattern: pattern.clone(),
+                operands: operands.iter().collect(),
+                span: *span,
+            },
+        }
+    }
+}
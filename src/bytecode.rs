@@ -0,0 +1,545 @@
+//! A compiler from expressions to bytecode for a stack machine, and the
+//! machine that runs it.
+//!
+//! Only the top-level expression is compiled. Function definitions and
+//! lambdas are rejected, so functions have to be defined with `eval`, and
+//! calling one from bytecode evaluates its body by walking the tree with
+//! `eval::call`. Variables aren't resolved to slots either: `Load`,
+//! `LoadFunction`, `Store` and `Increment` look their name up in the `Env`
+//! each time they run.
+
+use crate::eval::{self, Env, EvalError, Value};
+use crate::expression::{Arg, Expr};
+use crate::number::Number;
+use crate::operators::Fixity;
+use crate::token::{Span, TokenType};
+use rustc_hash::FxHashMap;
+use std::fmt;
+
+/// One instruction of a `Chunk`. Operands index its constants or names,
+/// or are the offset of the instruction to jump to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Op {
+    /// Push a constant.
+    Const(u32),
+    /// Push the value of a variable.
+    Load(u32),
+    /// Push the function a name calls, a variable or else a registered
+    /// function.
+    LoadFunction(u32),
+    /// Assign the top of the stack to a variable, leaving it there.
+    Store(u32),
+    /// `++` or `--` on a variable, pushing its new value for prefix and its
+    /// old value for postfix.
+    Increment {
+        name: u32,
+        op: TokenType,
+        fixity: Fixity,
+    },
+    /// Replace the top of the stack with a unary operator applied to it.
+    Unary(TokenType, Fixity),
+    /// Replace the top two values with a binary operator applied to them.
+    Binary(TokenType),
+    /// Swap the top two values, which puts a piped value after its callee.
+    Swap,
+    /// Pop a callee and that many arguments pushed after it, and push the
+    /// result of the call.
+    Call(u32),
+    Jump(u32),
+    /// Pop a number and jump if it's zero.
+    JumpIfFalse(u32),
+}
+
+/// The bytecode of one expression, with the node each instruction reports
+/// errors at.
+#[derive(Debug, Clone, Default)]
+pub struct Chunk {
+    code: Vec<Op>,
+    spans: Vec<Span>,
+    constants: Vec<Value>,
+    names: Vec<String>,
+}
+
+impl Chunk {
+    pub fn code(&self) -> &[Op] {
+        &self.code
+    }
+
+    pub fn constants(&self) -> &[Value] {
+        &self.constants
+    }
+
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+}
+
+/// The disassembly, one instruction a line with its offset and operands
+/// resolved:
+///
+/// ```text
+/// 0000  load           x
+/// 0001  jump-if-false  0004
+/// ```
+impl fmt::Display for Chunk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (offset, op) in self.code.iter().enumerate() {
+            let name = |i: u32| &self.names[i as usize];
+            let punctuator = |op: TokenType| op.punctuator().unwrap();
+            let (mnemonic, operand) = match *op {
+                Op::Const(i) => ("const", self.constants[i as usize].to_string()),
+                Op::Load(i) => ("load", name(i).clone()),
+                Op::LoadFunction(i) => ("load-function", name(i).clone()),
+                Op::Store(i) => ("store", name(i).clone()),
+                Op::Increment {
+                    name: i,
+                    op,
+                    fixity: Fixity::Prefix,
+                } => ("increment", format!("{}{}", punctuator(op), name(i))),
+                Op::Increment {
+                    name: i,
+                    op,
+                    fixity: Fixity::Postfix,
+                } => ("increment", format!("{}{}", name(i), punctuator(op))),
+                Op::Unary(op, Fixity::Prefix) => ("prefix", punctuator(op).to_string()),
+                Op::Unary(op, Fixity::Postfix) => ("postfix", punctuator(op).to_string()),
+                Op::Binary(op) => ("infix", punctuator(op).to_string()),
+                Op::Swap => ("swap", String::new()),
+                Op::Call(argc) => ("call", argc.to_string()),
+                Op::Jump(target) => ("jump", format!("{target:04}")),
+                Op::JumpIfFalse(target) => ("jump-if-false", format!("{target:04}")),
+            };
+            writeln!(
+                f,
+                "{}",
+                format!("{offset:04}  {mnemonic:<15}{operand}").trim_end()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Compile `expr` to bytecode that `Vm::run` evaluates like `eval` would.
+///
+/// Expressions that `eval` rejects whenever it reaches them are rejected
+/// here up front with the same error: mixfix, named and spread arguments,
+/// `++` and `--` on something other than a variable and invalid numbers.
+/// Function definitions and lambdas aren't compiled; define them with
+/// `eval` and call them from bytecode.
+pub fn compile(expr: &Expr) -> Result<Chunk, EvalError> {
+    let mut compiler = Compiler::default();
+    compiler.expr(expr)?;
+    Ok(compiler.chunk)
+}
+
+#[derive(Default)]
+struct Compiler {
+    chunk: Chunk,
+    // the index of each name in `chunk.names`
+    names: FxHashMap<String, u32>,
+}
+
+impl Compiler {
+    fn expr(&mut self, expr: &Expr) -> Result<(), EvalError> {
+        match expr {
+            Expr::Name { name, span } => {
+                let name = self.name(name);
+                self.emit(Op::Load(name), *span);
+            }
+            Expr::Number { literal, span } => {
+                let n = Number::parse(literal)
+                    .ok_or_else(|| EvalError::new(*span, format!("Invalid number `{literal}`.")))?;
+                let constant = self.constant(Value::Number(n));
+                self.emit(Op::Const(constant), *span);
+            }
+            Expr::Assign { name, right, span } => {
+                self.expr(right)?;
+                let name = self.name(name);
+                self.emit(Op::Store(name), *span);
+            }
+            Expr::FnDef { span, .. } | Expr::Lambda { span, .. } => {
+                return Err(EvalError::new(
+                    *span,
+                    "Functions can't be defined in bytecode.",
+                ));
+            }
+            Expr::Call { func, args, span } => {
+                self.callee(func, *span)?;
+                let argc = self.args(args)?;
+                self.emit(Op::Call(argc), *span);
+            }
+            Expr::Cond {
+                cond,
+                then_arm,
+                else_arm,
+                span,
+            } => {
+                self.expr(cond)?;
+                let to_else = self.emit(Op::JumpIfFalse(0), cond.span());
+                self.expr(then_arm)?;
+                let to_end = self.emit(Op::Jump(0), *span);
+                self.patch(to_else);
+                self.expr(else_arm)?;
+                self.patch(to_end);
+            }
+            Expr::Prefix { op, right, span } => match op {
+                TokenType::PlusPlus | TokenType::MinusMinus => {
+                    self.increment(right, *op, Fixity::Prefix, *span)?;
+                }
+                _ => {
+                    self.expr(right)?;
+                    self.emit(Op::Unary(*op, Fixity::Prefix), *span);
+                }
+            },
+            Expr::Postfix { left, op, span } => match op {
+                TokenType::PlusPlus | TokenType::MinusMinus => {
+                    self.increment(left, *op, Fixity::Postfix, *span)?;
+                }
+                _ => {
+                    self.expr(left)?;
+                    self.emit(Op::Unary(*op, Fixity::Postfix), *span);
+                }
+            },
+            Expr::Infix {
+                left,
+                op,
+                right,
+                span,
+            } => {
+                self.expr(left)?;
+                self.expr(right)?;
+                self.emit(Op::Binary(*op), *span);
+            }
+            Expr::Pipe { left, right, span } => {
+                self.expr(left)?;
+                let argc = match &**right {
+                    Expr::Call { func, args, .. } => {
+                        self.callee(func, *span)?;
+                        self.emit(Op::Swap, *span);
+                        self.args(args)?
+                    }
+                    right => {
+                        self.callee(right, *span)?;
+                        self.emit(Op::Swap, *span);
+                        0
+                    }
+                };
+                self.emit(Op::Call(argc + 1), *span);
+            }
+            Expr::Mixfix { pattern, span, .. } => {
+                return Err(EvalError::new(
+                    *span,
+                    format!("Mixfix `{pattern}` has no meaning to evaluate."),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // like `eval`, errors about a named callee point at the call
+    fn callee(&mut self, func: &Expr, span: Span) -> Result<(), EvalError> {
+        match func {
+            Expr::Name { name, .. } => {
+                let name = self.name(name);
+                self.emit(Op::LoadFunction(name), span);
+                Ok(())
+            }
+            func => self.expr(func),
+        }
+    }
+
+    fn args(&mut self, args: &[Arg]) -> Result<u32, EvalError> {
+        for arg in args {
+            match arg {
+                Arg::Positional(value) => self.expr(value)?,
+                Arg::Named { name, value } => {
+                    return Err(EvalError::new(
+                        value.span(),
+                        format!("Named argument `{name}` isn't supported."),
+                    ));
+                }
+                Arg::Spread(value) => {
+                    return Err(EvalError::new(
+                        value.span(),
+                        "Spread arguments aren't supported.",
+                    ));
+                }
+            }
+        }
+        Ok(args.len() as u32)
+    }
+
+    fn increment(
+        &mut self,
+        target: &Expr,
+        op: TokenType,
+        fixity: Fixity,
+        span: Span,
+    ) -> Result<(), EvalError> {
+        let Expr::Name { name, span: target } = target else {
+            return Err(EvalError::new(
+                span,
+                format!("`{}` needs a variable.", op.punctuator().unwrap()),
+            ));
+        };
+        let name = self.name(name);
+        self.emit(Op::Increment { name, op, fixity }, *target);
+        Ok(())
+    }
+
+    // the offset of the emitted instruction
+    fn emit(&mut self, op: Op, span: Span) -> usize {
+        self.chunk.code.push(op);
+        self.chunk.spans.push(span);
+        self.chunk.code.len() - 1
+    }
+
+    // point the jump at `offset` to the next instruction
+    fn patch(&mut self, offset: usize) {
+        let next = self.chunk.code.len() as u32;
+        match &mut self.chunk.code[offset] {
+            Op::Jump(target) | Op::JumpIfFalse(target) => *target = next,
+            op => unreachable!("{op:?} isn't a jump"),
+        }
+    }
+
+    fn name(&mut self, name: &str) -> u32 {
+        if let Some(&i) = self.names.get(name) {
+            return i;
+        }
+        let i = self.chunk.names.len() as u32;
+        self.chunk.names.push(name.to_string());
+        self.names.insert(name.to_string(), i);
+        i
+    }
+
+    fn constant(&mut self, value: Value) -> u32 {
+        self.chunk.constants.push(value);
+        self.chunk.constants.len() as u32 - 1
+    }
+}
+
+/// Runs chunks on a stack of values, which it keeps between runs.
+#[derive(Debug, Default)]
+pub struct Vm {
+    stack: Vec<Value>,
+}
+
+impl Vm {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run `chunk` with the variables, operators and functions in `env`.
+    pub fn run(&mut self, chunk: &Chunk, env: &mut Env) -> Result<Value, EvalError> {
+        self.stack.clear();
+        let mut pc = 0;
+        while let Some(&op) = chunk.code.get(pc) {
+            let span = chunk.spans[pc];
+            let name = |i: u32| chunk.names[i as usize].as_str();
+            pc += 1;
+            match op {
+                Op::Const(i) => self.stack.push(chunk.constants[i as usize].clone()),
+                Op::Load(i) => self.stack.push(eval::variable(name(i), env, span)?),
+                Op::LoadFunction(i) => self.stack.push(eval::named_callee(name(i), env, span)?),
+                Op::Store(i) => env.assign(name(i), self.top().clone()),
+                Op::Increment {
+                    name: i,
+                    op,
+                    fixity,
+                } => {
+                    let (old, new) = eval::increment_variable(name(i), op, env, span)?;
+                    let value = match fixity {
+                        Fixity::Prefix => new,
+                        Fixity::Postfix => old,
+                    };
+                    self.stack.push(Value::Number(value));
+                }
+                Op::Unary(op, fixity) => {
+                    let operand = self.pop();
+                    let result = eval::unary(op, fixity, &operand, env, span)?;
+                    self.stack.push(result);
+                }
+                Op::Binary(op) => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    let result = eval::infix(op, &left, &right, env, span)?;
+                    self.stack.push(result);
+                }
+                Op::Swap => {
+                    let len = self.stack.len();
+                    self.stack.swap(len - 1, len - 2);
+                }
+                Op::Call(argc) => {
+                    let args = self.stack.split_off(self.stack.len() - argc as usize);
+                    let func = self.pop();
+                    let result = eval::call(&func, &args, env, span)?;
+                    self.stack.push(result);
+                }
+                Op::Jump(target) => pc = target as usize,
+                Op::JumpIfFalse(target) => {
+                    if eval::expect_number(self.pop(), span)?.is_zero() {
+                        pc = target as usize;
+                    }
+                }
+            }
+        }
+        Ok(self.pop())
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("the stack underflowed")
+    }
+
+    fn top(&self) -> &Value {
+        self.stack.last().expect("the stack is empty")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::eval;
+    use crate::parser::BantamParser;
+
+    fn run(source: &str, env: &mut Env) -> Result<Value, EvalError> {
+        let chunk = compile(&BantamParser::new(source).parse_expression())?;
+        Vm::new().run(&chunk, env)
+    }
+
+    fn disassemble(source: &str) -> String {
+        compile(&BantamParser::new(source).parse_expression())
+            .unwrap()
+            .to_string()
+    }
+
+    // the VM and `eval` agree on the result and on the variables they leave
+    fn agree(source: &str, setup: &[&str]) {
+        let (mut vm_env, mut eval_env) = (Env::new(), Env::new());
+        for env in [&mut vm_env, &mut eval_env] {
+            for setup in setup {
+                eval(&BantamParser::new(setup).parse_expression(), env).unwrap();
+            }
+        }
+        let expr = BantamParser::new(source).parse_expression();
+        assert_eq!(
+            run(source, &mut vm_env),
+            eval(&expr, &mut eval_env),
+            "{source}"
+        );
+        for name in ["x", "y"] {
+            assert_eq!(
+                vm_env.get(name),
+                eval_env.get(name),
+                "{name} after {source}"
+            );
+        }
+    }
+
+    #[test]
+    fn same_results_as_eval() {
+        for source in [
+            "1 + 2 * 3 - 4 / 8",
+            "2 ^ 3 ^ 2",
+            "-(1.5) + +2",
+            "2 ^ 70 - 1",
+            "x = y = 3",
+            "x = 1 ? 2 : 3",
+            "0 ? (x = 1) : (y = 2)",
+            "1 ? 2 ? 3 : 4 : 5",
+            "x = 4 + x++ + ++x",
+            "x = 1 + --x - x--",
+            "3!",
+            "sqrt(16) + max(1, 2, 3)",
+            "16 |> sqrt",
+            "2 |> min(1, 3) |> abs",
+            "f(2) + f(f(1))",
+            "x |> f",
+            "f(x)",
+            "nope",
+            "x + nope(1)",
+            "x(1)",
+            "(1 + 2)(3)",
+            "sqrt(1, 2)",
+            "f(1, 2)",
+            "f ? 1 : 2",
+            "++f",
+            "f !",
+            "deep(0)",
+        ] {
+            agree(
+                source,
+                &["deep(n) = deep(n + 1)", "f(x) = x * 2", "x = 5", "y = 0"],
+            );
+        }
+    }
+
+    #[test]
+    fn same_errors_as_eval() {
+        for source in ["x + 1", "x++", "f(1)", "ln(0)", "1 / 0", "sqrt + 1"] {
+            agree(source, &[]);
+        }
+    }
+
+    #[test]
+    fn closures_defined_by_eval() {
+        let mut env = Env::new();
+        let define = "adder(n) = x => x + n";
+        eval(&BantamParser::new(define).parse_expression(), &mut env).unwrap();
+        let add = run("add = adder(10)", &mut env).unwrap();
+        assert!(matches!(add, Value::Function(_)));
+        assert_eq!(run("add(1) + (2 |> add)", &mut env), Ok(Value::from(23)));
+    }
+
+    #[test]
+    fn compile_errors() {
+        let error =
+            |source: &str| compile(&BantamParser::new(source).parse_expression()).unwrap_err();
+        assert_eq!(
+            error("1 + (x => x)"),
+            EvalError {
+                span: Span::new(4, 12),
+                message: "Functions can't be defined in bytecode.".to_string(),
+            }
+        );
+        assert_eq!(
+            error("1 ? 2 : (3)++"),
+            EvalError {
+                span: Span::new(8, 13),
+                message: "`++` needs a variable.".to_string(),
+            }
+        );
+        assert_eq!(
+            error("f(x: 1)").message,
+            "Named argument `x` isn't supported."
+        );
+    }
+
+    #[test]
+    fn disassembly() {
+        assert_eq!(
+            disassemble("y = x ? -x : x++ + 1"),
+            "\
+0000  load           x
+0001  jump-if-false  0005
+0002  load           x
+0003  prefix         -
+0004  jump           0008
+0005  increment      x++
+0006  const          1
+0007  infix          +
+0008  store          y
+"
+        );
+        assert_eq!(
+            disassemble("a |> f(2)"),
+            "\
+0000  load           a
+0001  load-function  f
+0002  swap
+0003  const          2
+0004  call           2
+"
+        );
+    }
+}
@@ -0,0 +1,481 @@
+use crate::eval::{self, EvalError, Native, Value};
+use crate::expression::{Arg, Expr};
+use crate::functions::FunctionRegistry;
+use crate::number::Number;
+use crate::operators::{Binary, Fixity, OperatorTable, Unary};
+use crate::token::{Span, TokenType};
+use std::fmt;
+use std::rc::Rc;
+
+type Node = Box<dyn Fn(&[Value]) -> Result<Value, EvalError>>;
+
+/// An expression compiled to nested closures for evaluating it many times
+/// with different inputs. Names, operators and functions were all resolved
+/// by `compile`, so evaluating it looks nothing up, except for operators
+/// with implementations for particular types, which depend on the operand.
+pub struct CompiledExpr {
+    root: Node,
+    inputs: Vec<String>,
+    span: Span,
+}
+
+impl CompiledExpr {
+    /// The names of the inputs, in the order `eval` takes their values.
+    pub fn inputs(&self) -> &[String] {
+        &self.inputs
+    }
+
+    /// Evaluate with `values[i]` as the input `inputs()[i]`. Fails at the
+    /// whole expression unless there's a value for each input.
+    pub fn eval(&self, values: &[Value]) -> Result<Value, EvalError> {
+        if values.len() != self.inputs.len() {
+            return Err(EvalError::new(
+                self.span,
+                format!(
+                    "The expression takes {} inputs, but was given {}.",
+                    self.inputs.len(),
+                    values.len()
+                ),
+            ));
+        }
+        (self.root)(values)
+    }
+}
+
+impl fmt::Debug for CompiledExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CompiledExpr")
+            .field("inputs", &self.inputs)
+            .finish()
+    }
+}
+
+/// Compile `expr` with `inputs` as its variables and the standard
+/// operators and functions, see `compile_with`.
+pub fn compile(expr: &Expr, inputs: &[&str]) -> Result<CompiledExpr, EvalError> {
+    compile_with(
+        expr,
+        inputs,
+        &OperatorTable::standard(),
+        &FunctionRegistry::standard(),
+    )
+}
+
+/// Compile `expr` with `inputs` as its variables, which `eval` would
+/// evaluate with an `Env` of `operators` and `functions`. Later changes to
+/// them don't affect the compiled expression.
+///
+/// Names that are neither inputs nor registered functions are errors, as
+/// are calls of anything but registered functions and calls with the
+/// wrong number of arguments. Compiled expressions only compute a value,
+/// so assignments, `++`, `--` and function definitions are errors too.
+/// So are operators `operators` doesn't define for any operand. The rest
+/// fail while evaluating, with the same errors as `eval`.
+pub fn compile_with(
+    expr: &Expr,
+    inputs: &[&str],
+    operators: &OperatorTable,
+    functions: &FunctionRegistry,
+) -> Result<CompiledExpr, EvalError> {
+    let compiler = Compiler {
+        inputs,
+        operators: Rc::new(operators.clone()),
+        functions,
+    };
+    Ok(CompiledExpr {
+        root: compiler.expr(expr)?,
+        inputs: inputs.iter().map(|input| input.to_string()).collect(),
+        span: expr.span(),
+    })
+}
+
+struct Compiler<'a> {
+    inputs: &'a [&'a str],
+    // shared by the operators that depend on the type of their operand
+    operators: Rc<OperatorTable>,
+    functions: &'a FunctionRegistry,
+}
+
+impl Compiler<'_> {
+    fn expr(&self, expr: &Expr) -> Result<Node, EvalError> {
+        Ok(match expr {
+            Expr::Name { name, span } => {
+                if let Some(slot) = self.slot(name) {
+                    Box::new(move |values| Ok(values[slot].clone()))
+                } else if let Some(func) = self.functions.get(name) {
+                    constant(Value::Function(func.clone()))
+                } else {
+                    return Err(EvalError::new(
+                        *span,
+                        format!("Undefined variable `{name}`."),
+                    ));
+                }
+            }
+            Expr::Number { literal, span } => match Number::parse(literal) {
+                Some(n) => constant(Value::Number(n)),
+                None => {
+                    return Err(EvalError::new(
+                        *span,
+                        format!("Invalid number `{literal}`."),
+                    ))
+                }
+            },
+            Expr::Assign { span, .. } => {
+                return Err(EvalError::new(
+                    *span,
+                    "Compiled expressions can't assign variables.",
+                ))
+            }
+            Expr::FnDef { span, .. } | Expr::Lambda { span, .. } => {
+                return Err(EvalError::new(
+                    *span,
+                    "Compiled expressions can't define functions.",
+                ))
+            }
+            Expr::Call { func, args, span } => {
+                let args = self.args(args)?;
+                self.call(func, args, *span)?
+            }
+            Expr::Cond {
+                cond,
+                then_arm,
+                else_arm,
+                ..
+            } => {
+                let span = cond.span();
+                let (cond, then_arm, else_arm) =
+                    (self.expr(cond)?, self.expr(then_arm)?, self.expr(else_arm)?);
+                Box::new(move |values| {
+                    if !eval::expect_number(cond(values)?, span)?.is_zero() {
+                        then_arm(values)
+                    } else {
+                        else_arm(values)
+                    }
+                })
+            }
+            Expr::Prefix { op, right, span } => self.unary(*op, Fixity::Prefix, right, *span)?,
+            Expr::Postfix { left, op, span } => self.unary(*op, Fixity::Postfix, left, *span)?,
+            Expr::Infix {
+                left,
+                op,
+                right,
+                span,
+            } => {
+                let f = self.infix_operator(*op, *span)?;
+                let (left, right, span) = (self.expr(left)?, self.expr(right)?, *span);
+                Box::new(move |values| {
+                    let (left, right) = (left(values)?, right(values)?);
+                    f(&left, &right).map_err(|message| EvalError::new(span, message))
+                })
+            }
+            Expr::Pipe { left, right, span } => {
+                let mut args = vec![self.expr(left)?];
+                let func = match &**right {
+                    Expr::Call {
+                        func, args: rest, ..
+                    } => {
+                        args.extend(self.args(rest)?);
+                        func
+                    }
+                    right => right,
+                };
+                self.call(func, args, *span)?
+            }
+            Expr::Mixfix { pattern, span, .. } => {
+                return Err(EvalError::new(
+                    *span,
+                    format!("Mixfix `{pattern}` has no meaning to evaluate."),
+                ))
+            }
+        })
+    }
+
+    fn slot(&self, name: &str) -> Option<usize> {
+        self.inputs.iter().position(|input| *input == name)
+    }
+
+    fn unary(
+        &self,
+        op: TokenType,
+        fixity: Fixity,
+        operand: &Expr,
+        span: Span,
+    ) -> Result<Node, EvalError> {
+        if matches!(op, TokenType::PlusPlus | TokenType::MinusMinus) {
+            return Err(EvalError::new(
+                span,
+                "Compiled expressions can't assign variables.",
+            ));
+        }
+        let f = self.unary_operator(op, fixity, span)?;
+        let operand = self.expr(operand)?;
+        Ok(Box::new(move |values| {
+            f(&operand(values)?).map_err(|message| EvalError::new(span, message))
+        }))
+    }
+
+    // `op`, looked up by the type of the operand only if the table has
+    // implementations for particular types
+    fn unary_operator(
+        &self,
+        op: TokenType,
+        fixity: Fixity,
+        span: Span,
+    ) -> Result<Rc<Unary>, EvalError> {
+        let fixity_name = match fixity {
+            Fixity::Prefix => "prefix",
+            Fixity::Postfix => "postfix",
+        };
+        if let Some(f) = self.operators.get_unary(op, fixity) {
+            return Ok(f);
+        }
+        if !self.operators.defines_unary(op, fixity) {
+            return Err(EvalError::new(span, undefined_operator(op, fixity_name)));
+        }
+        let operators = self.operators.clone();
+        Ok(Rc::new(move |operand| {
+            operators
+                .apply_unary(op, fixity, operand)
+                .unwrap_or_else(|| Err(undefined_operator(op, fixity_name)))
+        }))
+    }
+
+    // the same for the left operand of an infix operator
+    fn infix_operator(&self, op: TokenType, span: Span) -> Result<Rc<Binary>, EvalError> {
+        if let Some(f) = self.operators.get_infix(op) {
+            return Ok(f);
+        }
+        if !self.operators.defines_infix(op) {
+            return Err(EvalError::new(span, undefined_operator(op, "infix")));
+        }
+        let operators = self.operators.clone();
+        Ok(Rc::new(move |left, right| {
+            operators
+                .apply_infix(op, left, right)
+                .unwrap_or_else(|| Err(undefined_operator(op, "infix")))
+        }))
+    }
+
+    fn args(&self, args: &[Arg]) -> Result<Vec<Node>, EvalError> {
+        args.iter()
+            .map(|arg| match arg {
+                Arg::Positional(value) => self.expr(value),
+                Arg::Named { name, value } => Err(EvalError::new(
+                    value.span(),
+                    format!("Named argument `{name}` isn't supported."),
+                )),
+                Arg::Spread(value) => Err(EvalError::new(
+                    value.span(),
+                    "Spread arguments aren't supported.",
+                )),
+            })
+            .collect()
+    }
+
+    // a call of the registered function `func`, with errors pointing at the
+    // call like `eval`'s
+    fn call(&self, func: &Expr, args: Vec<Node>, span: Span) -> Result<Node, EvalError> {
+        let native = match func {
+            Expr::Name { name, .. } if self.slot(name).is_some() => {
+                return Err(EvalError::new(
+                    span,
+                    format!("Compiled expressions can't call the input `{name}`."),
+                ))
+            }
+            Expr::Name { name, .. } => {
+                let Some(func) = self.functions.get(name) else {
+                    return Err(EvalError::new(
+                        span,
+                        format!("Undefined function `{name}`."),
+                    ));
+                };
+                if !func.arity.accepts(args.len()) {
+                    return Err(EvalError::new(
+                        span,
+                        format!(
+                            "`{name}` takes {}, but was given {}.",
+                            func.arity,
+                            args.len()
+                        ),
+                    ));
+                }
+                func.native().expect("registered functions are native")
+            }
+            _ => {
+                return Err(EvalError::new(
+                    span,
+                    "Compiled expressions can only call functions by name.",
+                ))
+            }
+        };
+        Ok(call(native, args, span))
+    }
+}
+
+fn call(native: Rc<Native>, args: Vec<Node>, span: Span) -> Node {
+    Box::new(move |values| {
+        let args = args
+            .iter()
+            .map(|arg| arg(values))
+            .collect::<Result<Vec<_>, _>>()?;
+        native(&args).map_err(|message| EvalError::new(span, message))
+    })
+}
+
+fn constant(value: Value) -> Node {
+    Box::new(move |_| Ok(value.clone()))
+}
+
+fn undefined_operator(op: TokenType, fixity: &str) -> String {
+    format!(
+        "The {fixity} operator `{}` is not defined.",
+        op.punctuator().unwrap()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::{eval, Env, ValueType};
+    use crate::functions::Arity;
+    use crate::parser::BantamParser;
+
+    fn compiled(source: &str, inputs: &[&str]) -> CompiledExpr {
+        compile(&BantamParser::new(source).parse_expression(), inputs).unwrap()
+    }
+
+    fn compile_error(source: &str, inputs: &[&str]) -> EvalError {
+        compile(&BantamParser::new(source).parse_expression(), inputs).unwrap_err()
+    }
+
+    #[test]
+    fn different_inputs() {
+        let price = compiled("qty ? max(base * qty - 5, 0) : 0", &["base", "qty"]);
+        assert_eq!(price.inputs(), ["base", "qty"]);
+        for (base, qty, total) in [(2, 10, 15), (1, 3, 0), (7, 0, 0)] {
+            assert_eq!(
+                price.eval(&[Value::from(base), Value::from(qty)]),
+                Ok(Value::from(total))
+            );
+        }
+    }
+
+    #[test]
+    fn same_results_as_eval() {
+        let inputs = ["x", "y"];
+        for source in [
+            "1 + 2 * 3 - 4 / 8",
+            "x ^ y ^ 2 - -x",
+            "y ? x / y : 0",
+            "3! + ~x + !y",
+            "sqrt(abs(x)) + min(x, y, 2)",
+            "x |> max(y) |> round",
+            "x / y",
+            "sqrt(y)",
+            "sqrt + 1",
+        ] {
+            let compiled = compiled(source, &inputs);
+            for (x, y) in [
+                (Value::from(2), Value::from(0)),
+                (Value::from(-1.5), Value::from(4)),
+            ] {
+                let mut env = Env::new();
+                env.set("x", x.clone());
+                env.set("y", y.clone());
+                let expr = BantamParser::new(source).parse_expression();
+                assert_eq!(compiled.eval(&[x, y]), eval(&expr, &mut env), "{source}");
+            }
+        }
+    }
+
+    #[test]
+    fn other_operators_and_functions() {
+        let mut operators = OperatorTable::standard();
+        operators.infix_for(TokenType::Slash, ValueType::Int, |a, b| match (a, b) {
+            (Value::Number(Number::Int(a)), Value::Number(Number::Int(b))) if *b != 0 => {
+                Ok(Value::from(a.div_euclid(*b)))
+            }
+            _ => Err("Integer division by zero.".to_string()),
+        });
+        let mut functions = FunctionRegistry::standard();
+        functions.register("double", Arity::Exactly(1), |args| {
+            Ok((args[0].as_number()? * &Number::Int(2)).into())
+        });
+        let mut env = Env::with_operators(operators.clone());
+        env.functions()
+            .register("double", Arity::Exactly(1), |args| {
+                Ok((args[0].as_number()? * &Number::Int(2)).into())
+            });
+
+        let source = "double(x / 2) + x";
+        let compiled = compile_with(
+            &BantamParser::new(source).parse_expression(),
+            &["x"],
+            &operators,
+            &functions,
+        )
+        .unwrap();
+        for x in [Value::from(7), Value::from(7.0)] {
+            env.set("x", x.clone());
+            let expr = BantamParser::new(source).parse_expression();
+            assert_eq!(compiled.eval(&[x]), eval(&expr, &mut env), "{source}");
+        }
+        assert_eq!(compiled.eval(&[Value::from(7)]), Ok(Value::from(13)));
+
+        let error = compile_with(
+            &BantamParser::new("x + 2").parse_expression(),
+            &["x"],
+            &OperatorTable::empty(),
+            &functions,
+        );
+        assert_eq!(
+            error.unwrap_err().message,
+            "The infix operator `+` is not defined."
+        );
+    }
+
+    #[test]
+    fn wrong_number_of_inputs() {
+        assert_eq!(
+            compiled(" x + y", &["x", "y"]).eval(&[Value::from(1)]),
+            Err(EvalError {
+                span: Span::new(1, 6),
+                message: "The expression takes 2 inputs, but was given 1.".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn unknown_names() {
+        assert_eq!(
+            compile_error("a + bb * 2", &["a"]),
+            EvalError {
+                span: Span::new(4, 6),
+                message: "Undefined variable `bb`.".to_string(),
+            }
+        );
+        assert_eq!(
+            compile_error("1 + f(2)", &["x"]),
+            EvalError {
+                span: Span::new(4, 8),
+                message: "Undefined function `f`.".to_string(),
+            }
+        );
+        assert_eq!(
+            compile_error("x(2)", &["x"]).message,
+            "Compiled expressions can't call the input `x`."
+        );
+        assert_eq!(
+            compile_error("sqrt(1, 2)", &[]).message,
+            "`sqrt` takes 1 argument, but was given 2."
+        );
+    }
+
+    #[test]
+    fn only_values() {
+        for source in ["x = 1", "x++", "f(x) = x", "1 + (x => x)"] {
+            let error = compile_error(source, &["x"]).message;
+            assert!(error.starts_with("Compiled expressions can't"), "{error}");
+        }
+    }
+}
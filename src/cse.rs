@@ -0,0 +1,364 @@
+use crate::builder::{AstBuilder, Boxed, ExprNode};
+use crate::expression::{Arg, Expr};
+use crate::hashcons::{HashCons, NodeId};
+use crate::token::{Span, TokenType};
+use rustc_hash::{FxHashMap, FxHashSet};
+use std::borrow::Cow;
+
+/// A subtree that occurs more than once in an expression.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Repeat<'a, 'source> {
+    /// The first occurrence.
+    pub expr: &'a Expr<'source>,
+    /// Where each occurrence is, left to right.
+    pub spans: Vec<Span>,
+}
+
+/// The subtrees of `expr` that occur more than once, spans aside, in the
+/// order their first occurrences end. Names and numbers aren't worth
+/// reporting and are left out; a repeat inside another repeat is reported
+/// as well, with all of its occurrences.
+pub fn repeats<'a, 'source>(expr: &'a Expr<'source>) -> Vec<Repeat<'a, 'source>> {
+    let mut seen: Vec<(NodeId, &Expr)> = vec![];
+    HashCons::default().add_with(expr, &mut |id, expr| seen.push((id, expr)));
+    let mut by_id: FxHashMap<NodeId, usize> = FxHashMap::default();
+    let mut repeats: Vec<Repeat> = vec![];
+    for (id, expr) in seen {
+        if is_leaf(expr) {
+            continue;
+        }
+        let index = *by_id.entry(id).or_insert_with(|| {
+            repeats.push(Repeat {
+                expr,
+                spans: vec![],
+            });
+            repeats.len() - 1
+        });
+        repeats[index].spans.push(expr.span());
+    }
+    repeats.retain(|repeat| repeat.spans.len() > 1);
+    repeats
+}
+
+/// An expression with its repeated subtrees factored out into temporaries.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Factored<'source> {
+    /// One `temp = subtree` assignment per temporary, each only using the
+    /// temporaries before it.
+    pub lets: Vec<Expr<'source>>,
+    /// What's left of the expression, using the temporaries.
+    pub body: Expr<'source>,
+}
+
+/// Factor every repeated subtree of `expr` out into a `let`-style
+/// temporary, outermost first: in `f(a + b) * f(a + b)` that is `f(a + b)`,
+/// and `a + b` isn't repeated any more once it has been. Temporaries are
+/// named `ta`, `tb`, ... skipping names `expr` already uses, and keep the
+/// spans of their first occurrence.
+///
+/// Temporaries are evaluated before the rest, so subtrees that assign, or
+/// read a variable that is assigned anywhere in `expr`, aren't factored, and
+/// calls are taken not to assign. Neither are subtrees repeated only in the
+/// arms of a conditional, which may not be evaluated at all.
+pub fn factor<'source>(expr: &Expr<'source>) -> Factored<'source> {
+    let mut nodes = HashCons::default();
+    let mut ids = vec![];
+    let root = nodes.add_with(expr, &mut |id, _| ids.push(id));
+
+    // Children have smaller ids than their parents, so their flags are set
+    // first.
+    let assigned = assigned(expr);
+    let mut pinned = vec![false; nodes.len()];
+    for index in 0..nodes.len() {
+        let node = &nodes[NodeId::from_index(index)];
+        pinned[index] = match node {
+            ExprNode::Assign { .. } | ExprNode::FnDef { .. } => true,
+            ExprNode::Prefix { op, .. } | ExprNode::Postfix { op, .. } => is_increment(*op),
+            ExprNode::Name { name, .. } => assigned.contains(name),
+            _ => false,
+        } || node.children().iter().any(|child| pinned[child.index()]);
+    }
+
+    // A temporary is used wherever its node occurs, but its own subtree only
+    // occurs once more, in its definition. Going down from the root settles
+    // each node's count before it is handed on.
+    let mut counts = vec![0; nodes.len()];
+    counts[root.index()] = 1;
+    let mut temps = FxHashMap::default();
+    let taken = names(expr);
+    let mut next = 0;
+    for index in (0..nodes.len()).rev() {
+        let node = &nodes[NodeId::from_index(index)];
+        if matches!(node, ExprNode::FnDef { .. } | ExprNode::Lambda { .. }) {
+            // the body is in the scope of the parameters, and stays as it is
+            continue;
+        }
+        if counts[index] > 1
+            && !pinned[index]
+            && !matches!(node, ExprNode::Name { .. } | ExprNode::Number { .. })
+        {
+            counts[index] = 1;
+            temps.insert(NodeId::from_index(index), temp_name(&taken, &mut next));
+        }
+        let always_evaluated = match node {
+            ExprNode::Cond { cond, .. } => vec![cond],
+            node => node.children(),
+        };
+        for child in always_evaluated {
+            counts[child.index()] += counts[index];
+        }
+    }
+
+    let mut factoring = Factoring {
+        ids: ids.into_iter(),
+        temps,
+        lets: FxHashMap::default(),
+        bodies: 0,
+    };
+    let body = factoring.rebuild(expr);
+    let mut lets: Vec<_> = factoring.lets.into_iter().collect();
+    lets.sort_by_key(|(id, _)| *id);
+    Factored {
+        lets: lets.into_iter().map(|(_, assign)| assign).collect(),
+        body,
+    }
+}
+
+fn is_leaf(expr: &Expr) -> bool {
+    matches!(expr, Expr::Name { .. } | Expr::Number { .. })
+}
+
+struct Factoring<'source> {
+    // the ids of the subtrees, in the order `rebuild` comes across them
+    ids: std::vec::IntoIter<NodeId>,
+    temps: FxHashMap<NodeId, String>,
+    lets: FxHashMap<NodeId, Expr<'source>>,
+    // how many function bodies `rebuild` is in
+    bodies: usize,
+}
+
+impl<'source> Factoring<'source> {
+    fn rebuild(&mut self, expr: &Expr<'source>) -> Expr<'source> {
+        let body = matches!(expr, Expr::FnDef { .. } | Expr::Lambda { .. }) as usize;
+        self.bodies += body;
+        let node = ExprNode::view(expr).map(|child| Box::new(self.rebuild(child)));
+        self.bodies -= body;
+        let id = self.ids.next().unwrap();
+        let rebuilt = *Boxed.build(node);
+        let Some(temp) = self.temps.get(&id).filter(|_| self.bodies == 0) else {
+            return rebuilt;
+        };
+        let span = expr.span();
+        self.lets.entry(id).or_insert_with(|| Expr::Assign {
+            name: Cow::Owned(temp.clone()),
+            right: Box::new(rebuilt),
+            span,
+        });
+        Expr::Name {
+            name: Cow::Owned(temp.clone()),
+            span,
+        }
+    }
+}
+
+// every name that appears in `expr`, so temporaries don't shadow any
+fn names<'source>(expr: &Expr<'source>) -> FxHashSet<Cow<'source, str>> {
+    let mut names = FxHashSet::default();
+    let mut pending = vec![expr];
+    while let Some(expr) = pending.pop() {
+        match expr {
+            Expr::Name { name, .. } | Expr::Assign { name, .. } => {
+                names.insert(name.clone());
+            }
+            Expr::FnDef { name, params, .. } => {
+                names.insert(name.clone());
+                names.extend(params.iter().cloned());
+            }
+            Expr::Lambda { param, .. } => {
+                names.insert(param.clone());
+            }
+            Expr::Call { args, .. } => {
+                for arg in args {
+                    if let Arg::Named { name, .. } = arg {
+                        names.insert(name.clone());
+                    }
+                }
+            }
+            _ => {}
+        }
+        pending.extend(ExprNode::view(expr).children().into_iter().copied());
+    }
+    names
+}
+
+// the variables `expr` assigns or increments anywhere
+fn assigned<'source>(expr: &Expr<'source>) -> FxHashSet<Cow<'source, str>> {
+    let mut assigned = FxHashSet::default();
+    let mut pending = vec![expr];
+    while let Some(expr) = pending.pop() {
+        match expr {
+            Expr::Assign { name, .. } | Expr::FnDef { name, .. } => {
+                assigned.insert(name.clone());
+            }
+            Expr::Prefix {
+                op, right: target, ..
+            }
+            | Expr::Postfix {
+                left: target, op, ..
+            } if is_increment(*op) => {
+                if let Expr::Name { name, .. } = &**target {
+                    assigned.insert(name.clone());
+                }
+            }
+            _ => {}
+        }
+        pending.extend(ExprNode::view(expr).children().into_iter().copied());
+    }
+    assigned
+}
+
+fn is_increment(op: TokenType) -> bool {
+    matches!(op, TokenType::PlusPlus | TokenType::MinusMinus)
+}
+
+// `ta` to `tz`, then `taa`, `tab`, ...; the lexer only takes letters in names
+fn temp_name(taken: &FxHashSet<Cow<str>>, next: &mut usize) -> String {
+    loop {
+        let mut n = *next;
+        *next += 1;
+        let mut suffix = vec![];
+        loop {
+            suffix.push(b'a' + (n % 26) as u8);
+            if n < 26 {
+                break;
+            }
+            n = n / 26 - 1;
+        }
+        suffix.reverse();
+        let name = format!("t{}", String::from_utf8(suffix).unwrap());
+        if !taken.contains(name.as_str()) {
+            return name;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::BantamParser;
+
+    fn factored(source: &str) -> Vec<String> {
+        let expr = BantamParser::new(source).parse_expression();
+        let Factored { lets, body } = factor(&expr);
+        lets.iter()
+            .chain([&body])
+            .map(|expr| expr.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn reports_repeats_with_spans() {
+        let expr = BantamParser::new("f(a, b) * f(a, b) + f(a,b)").parse_expression();
+        let found = repeats(&expr);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].expr.to_string(), "f(a, b)");
+        assert_eq!(
+            found[0].spans,
+            [Span::new(0, 7), Span::new(10, 17), Span::new(20, 26)]
+        );
+
+        let expr = BantamParser::new("g(-x) + g(-x) + -x").parse_expression();
+        let found: Vec<_> = repeats(&expr)
+            .iter()
+            .map(|r| (r.expr.to_string(), r.spans.len()))
+            .collect();
+        assert_eq!(found, [("(-x)".to_string(), 3), ("g((-x))".to_string(), 2)]);
+    }
+
+    #[test]
+    fn factors_outermost_repeats() {
+        assert_eq!(
+            factored("f(a, b) * f(a, b) + f(a, b)"),
+            ["(ta = f(a, b))", "((ta * ta) + ta)"]
+        );
+        // `a + b` still repeats inside the factored call
+        assert_eq!(
+            factored("g(a + b, a + b) - g(a + b, a + b)"),
+            ["(tb = (a + b))", "(ta = g(tb, tb))", "(ta - ta)"]
+        );
+        assert_eq!(
+            factored("h(a * c) + h(a * c) * (a * c)"),
+            ["(tb = (a * c))", "(ta = h(tb))", "(ta + (ta * tb))"]
+        );
+        assert_eq!(factored("a + a"), ["(a + a)"]);
+    }
+
+    #[test]
+    fn temporaries_avoid_used_names() {
+        assert_eq!(
+            factored("ta = -tb + f(tc: -tb)"),
+            ["(td = (-tb))", "(ta = (td + f(tc: td)))"]
+        );
+    }
+
+    #[test]
+    fn function_bodies_stay_whole() {
+        // `-x` means something else inside `f`
+        assert_eq!(
+            factored("f(x) = -x * -x + g(-x)"),
+            ["(f(x) = (((-x) * (-x)) + g((-x))))"]
+        );
+        assert_eq!(
+            factored("(f(x) = -x) + -x * -x"),
+            ["(ta = (-x))", "((f(x) = (-x)) + (ta * ta))"]
+        );
+        assert_eq!(
+            factored("g(x => -x * -x, -x * -x)"),
+            ["(ta = (-x))", "g((x => ((-x) * (-x))), (ta * ta))"]
+        );
+    }
+
+    #[test]
+    fn assignments_stay_in_order() {
+        assert_eq!(factored("x++ + x++"), ["((x++) + (x++))"]);
+        assert_eq!(
+            factored("(x = x + 1) * (x = x + 1)"),
+            ["((x = (x + 1)) * (x = (x + 1)))"]
+        );
+        // `x + y` reads `x` before the second assignment
+        assert_eq!(
+            factored("(x + y) * (x + y) + (x = 2)"),
+            ["(((x + y) * (x + y)) + (x = 2))"]
+        );
+        assert_eq!(
+            factored("(z = f(a) * f(a)) + --w"),
+            ["(ta = f(a))", "((z = (ta * ta)) + (--w))"]
+        );
+    }
+
+    #[test]
+    fn conditional_arms_stay_conditional() {
+        assert_eq!(
+            factored("c ? 1 / x + 1 / x : 0"),
+            ["(c ? ((1 / x) + (1 / x)) : 0)"]
+        );
+        assert_eq!(factored("(c ? -x : 0) + -x"), ["((c ? (-x) : 0) + (-x))"]);
+        // evaluated anyway outside of the arm, it can be used inside too
+        assert_eq!(
+            factored("-x * -x + (-x ? 1 : -x)"),
+            ["(ta = (-x))", "((ta * ta) + (ta ? 1 : ta))"]
+        );
+    }
+
+    #[test]
+    fn temporaries_keep_spans() {
+        let expr = BantamParser::new("-x * -x").parse_expression();
+        let Factored { lets, body } = factor(&expr);
+        assert_eq!(lets[0].span(), Span::new(0, 2));
+        let Expr::Infix { left, right, .. } = body else {
+            panic!("expected an infix");
+        };
+        assert_eq!(left.span(), Span::new(0, 2));
+        assert_eq!(right.span(), Span::new(5, 7));
+    }
+}
@@ -0,0 +1,374 @@
+use crate::builder::ExprNode;
+use crate::expression::{Arg, Expr};
+use crate::simplify::Simplifier;
+use crate::token::{Span, TokenType};
+use std::borrow::Cow;
+
+#[derive(Debug, PartialEq)]
+pub struct DiffError {
+    /// The node that couldn't be differentiated.
+    pub span: Span,
+    pub message: String,
+}
+
+impl DiffError {
+    fn new(span: Span, message: impl Into<String>) -> Self {
+        Self {
+            span,
+            message: message.into(),
+        }
+    }
+}
+
+/// The derivative of `expr` with respect to the variable `var`, simplified.
+///
+/// Sums, differences, products, quotients and powers are differentiated by
+/// their rules, and calls of `sin`, `cos`, `exp`, `ln` and `sqrt` by the
+/// chain rule; a pipe into one of them is a call. A conditional's
+/// derivative is the conditional of its arms' derivatives. Other names are
+/// constants, and anything else, like an assignment or a call of another
+/// function, is an error.
+///
+/// The result is simplified with `x * 0` as `0`, which drops the terms of
+/// constant factors but assumes the variables are finite. New nodes take
+/// the span of the expression they are the derivative of.
+pub fn diff<'source>(expr: &Expr<'source>, var: &str) -> Result<Expr<'source>, DiffError> {
+    let derivative = Differentiate { var }.expr(expr)?;
+    Ok(Simplifier::new().with_zero_products().simplify(derivative))
+}
+
+struct Differentiate<'a> {
+    var: &'a str,
+}
+
+impl Differentiate<'_> {
+    fn expr<'source>(&self, expr: &Expr<'source>) -> Result<Expr<'source>, DiffError> {
+        let span = expr.span();
+        Ok(match expr {
+            Expr::Name { name, .. } if name == self.var => number("1", span),
+            Expr::Name { .. } | Expr::Number { .. } => number("0", span),
+            Expr::Prefix {
+                op: op @ (TokenType::Plus | TokenType::Minus),
+                right,
+                ..
+            } => Expr::Prefix {
+                op: *op,
+                right: Box::new(self.expr(right)?),
+                span,
+            },
+            Expr::Infix {
+                left, op, right, ..
+            } => self.infix(left, *op, right, span)?,
+            Expr::Cond {
+                cond,
+                then_arm,
+                else_arm,
+                ..
+            } => Expr::Cond {
+                cond: cond.clone(),
+                then_arm: Box::new(self.expr(then_arm)?),
+                else_arm: Box::new(self.expr(else_arm)?),
+                span,
+            },
+            Expr::Call { func, args, .. } => {
+                let args = args
+                    .iter()
+                    .map(|arg| match arg {
+                        Arg::Positional(value) => Ok(value),
+                        Arg::Named { value, .. } | Arg::Spread(value) => Err(DiffError::new(
+                            value.span(),
+                            "Only positional arguments can be differentiated.",
+                        )),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.call(func, &args, span)?
+            }
+            Expr::Pipe { left, right, .. } => match &**right {
+                Expr::Call { func, args, .. } if args.is_empty() => {
+                    self.call(func, &[left], span)?
+                }
+                Expr::Call { .. } => {
+                    return Err(DiffError::new(
+                        span,
+                        "Only functions of one argument can be differentiated.",
+                    ))
+                }
+                func => self.call(func, &[left], span)?,
+            },
+            _ => {
+                return Err(DiffError::new(
+                    span,
+                    "Only arithmetic and calls of known functions can be differentiated.",
+                ))
+            }
+        })
+    }
+
+    fn infix<'source>(
+        &self,
+        u: &Expr<'source>,
+        op: TokenType,
+        v: &Expr<'source>,
+        span: Span,
+    ) -> Result<Expr<'source>, DiffError> {
+        let (du, dv) = (self.expr(u)?, self.expr(v)?);
+        let (u, v) = (u.clone(), v.clone());
+        Ok(match op {
+            TokenType::Plus | TokenType::Minus => infix(du, op, dv, span),
+            // u'v + uv'
+            TokenType::Asterisk => infix(
+                infix(du, TokenType::Asterisk, v, span),
+                TokenType::Plus,
+                infix(u, TokenType::Asterisk, dv, span),
+                span,
+            ),
+            // (u'v - uv') / v^2
+            TokenType::Slash => infix(
+                infix(
+                    infix(du, TokenType::Asterisk, v.clone(), span),
+                    TokenType::Minus,
+                    infix(u, TokenType::Asterisk, dv, span),
+                    span,
+                ),
+                TokenType::Slash,
+                infix(v, TokenType::Caret, number("2", span), span),
+                span,
+            ),
+            // v u^(v - 1) u'
+            TokenType::Caret if !self.mentions(&v) => infix(
+                infix(
+                    v.clone(),
+                    TokenType::Asterisk,
+                    infix(
+                        u,
+                        TokenType::Caret,
+                        infix(v, TokenType::Minus, number("1", span), span),
+                        span,
+                    ),
+                    span,
+                ),
+                TokenType::Asterisk,
+                du,
+                span,
+            ),
+            // u^v ln(u) v'
+            TokenType::Caret if !self.mentions(&u) => infix(
+                infix(
+                    infix(u.clone(), TokenType::Caret, v, span),
+                    TokenType::Asterisk,
+                    call("ln", u, span),
+                    span,
+                ),
+                TokenType::Asterisk,
+                dv,
+                span,
+            ),
+            // u^v (v' ln(u) + v u' / u)
+            TokenType::Caret => infix(
+                infix(u.clone(), TokenType::Caret, v.clone(), span),
+                TokenType::Asterisk,
+                infix(
+                    infix(dv, TokenType::Asterisk, call("ln", u.clone(), span), span),
+                    TokenType::Plus,
+                    infix(
+                        infix(v, TokenType::Asterisk, du, span),
+                        TokenType::Slash,
+                        u,
+                        span,
+                    ),
+                    span,
+                ),
+                span,
+            ),
+            op => {
+                return Err(DiffError::new(
+                    span,
+                    format!(
+                        "The operator `{}` can't be differentiated.",
+                        op.punctuator().unwrap()
+                    ),
+                ))
+            }
+        })
+    }
+
+    // `f(u)` by the chain rule, f'(u) u'
+    fn call<'source>(
+        &self,
+        func: &Expr<'source>,
+        args: &[&Expr<'source>],
+        span: Span,
+    ) -> Result<Expr<'source>, DiffError> {
+        let Expr::Name { name, .. } = func else {
+            return Err(DiffError::new(
+                span,
+                "Only calls of known functions can be differentiated.",
+            ));
+        };
+        let [u] = args else {
+            return Err(DiffError::new(
+                span,
+                format!(
+                    "`{name}` of {} arguments can't be differentiated.",
+                    args.len()
+                ),
+            ));
+        };
+        let u = (*u).clone();
+        let df = match &**name {
+            "sin" => call("cos", u.clone(), span),
+            "cos" => Expr::Prefix {
+                op: TokenType::Minus,
+                right: Box::new(call("sin", u.clone(), span)),
+                span,
+            },
+            "exp" => call("exp", u.clone(), span),
+            "ln" => infix(number("1", span), TokenType::Slash, u.clone(), span),
+            "sqrt" => infix(
+                number("1", span),
+                TokenType::Slash,
+                infix(
+                    number("2", span),
+                    TokenType::Asterisk,
+                    call("sqrt", u.clone(), span),
+                    span,
+                ),
+                span,
+            ),
+            _ => {
+                return Err(DiffError::new(
+                    span,
+                    format!("The function `{name}` can't be differentiated."),
+                ))
+            }
+        };
+        Ok(infix(df, TokenType::Asterisk, self.expr(&u)?, span))
+    }
+
+    // whether `expr` depends on the variable
+    fn mentions(&self, expr: &Expr) -> bool {
+        match expr {
+            Expr::Name { name, .. } => name == self.var,
+            expr => ExprNode::view(expr)
+                .children()
+                .into_iter()
+                .any(|child| self.mentions(child)),
+        }
+    }
+}
+
+fn number<'source>(literal: &'static str, span: Span) -> Expr<'source> {
+    Expr::Number {
+        literal: Cow::Borrowed(literal),
+        span,
+    }
+}
+
+fn infix<'source>(
+    left: Expr<'source>,
+    op: TokenType,
+    right: Expr<'source>,
+    span: Span,
+) -> Expr<'source> {
+    Expr::Infix {
+        left: Box::new(left),
+        op,
+        right: Box::new(right),
+        span,
+    }
+}
+
+fn call<'source>(name: &'static str, arg: Expr<'source>, span: Span) -> Expr<'source> {
+    Expr::Call {
+        func: Box::new(Expr::Name {
+            name: Cow::Borrowed(name),
+            span,
+        }),
+        args: vec![Arg::Positional(arg)],
+        span,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::{eval, Env, Value};
+    use crate::parser::BantamParser;
+
+    fn derivative(source: &str) -> String {
+        let expr = BantamParser::new(source).parse_expression();
+        diff(&expr, "x").unwrap().to_string()
+    }
+
+    fn error(source: &str) -> DiffError {
+        diff(&BantamParser::new(source).parse_expression(), "x").unwrap_err()
+    }
+
+    #[test]
+    fn rules() {
+        assert_eq!(derivative("3 * x + y - 2"), "3");
+        assert_eq!(derivative("x * x"), "(x + x)");
+        assert_eq!(derivative("x ^ 3"), "(3 * (x ^ 2))");
+        assert_eq!(derivative("1 / x"), "((-1) / (x ^ 2))");
+        assert_eq!(derivative("2 ^ x"), "((2 ^ x) * ln(2))");
+        assert_eq!(derivative("-(x * y)"), "(-y)");
+        assert_eq!(derivative("c ? x : 2 * x"), "(c ? 1 : 2)");
+    }
+
+    #[test]
+    fn chain_rule() {
+        assert_eq!(derivative("sin(x ^ 2)"), "(cos((x ^ 2)) * (2 * x))");
+        assert_eq!(derivative("exp(3 * x)"), "(exp((3 * x)) * 3)");
+        assert_eq!(derivative("ln(x)"), "(1 / x)");
+        assert_eq!(derivative("x |> cos"), "(-sin(x))");
+        assert_eq!(derivative("sin(y)"), "0");
+    }
+
+    #[test]
+    fn matches_the_slope() {
+        for source in [
+            "x ^ 3 - 2 * x",
+            "sin(x) * exp(x)",
+            "ln(x) / x",
+            "x ^ x",
+            "sqrt(x ^ 2 + 1)",
+        ] {
+            let expr = BantamParser::new(source).parse_expression();
+            let derivative = diff(&expr, "x").unwrap();
+            let at = |expr: &Expr, x: f64| {
+                let mut env = Env::new();
+                env.set("x", Value::from(x));
+                match eval(expr, &mut env) {
+                    Ok(Value::Number(n)) => n.to_f64(),
+                    result => panic!("{source} gave {result:?}"),
+                }
+            };
+            let (x, h) = (1.5, 1e-6);
+            let slope = (at(&expr, x + h) - at(&expr, x - h)) / (2.0 * h);
+            assert!(
+                (at(&derivative, x) - slope).abs() < 1e-4,
+                "{source}: {} vs {slope}",
+                at(&derivative, x)
+            );
+        }
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            error("1 + f(x)"),
+            DiffError {
+                span: Span::new(4, 8),
+                message: "The function `f` can't be differentiated.".to_string(),
+            }
+        );
+        assert_eq!(
+            error("x = 2 * x").message,
+            "Only arithmetic and calls of known functions can be differentiated."
+        );
+        assert_eq!(
+            error("max(x, 1)").message,
+            "`max` of 2 arguments can't be differentiated."
+        );
+    }
+}
@@ -0,0 +1,185 @@
+use crate::expression::{Arg, Expr};
+use std::fmt::Write;
+
+/// Write `expr` as a Graphviz `digraph`.
+///
+/// Operators are labeled with their punctuator, postfix ones as `_!` to tell
+/// them apart from prefix `!`, and names and numbers with their text. Edges
+/// are drawn in operand order; those of a conditional are labeled `cond`,
+/// `then` and `else`, and those of a call `func`, the argument's position,
+/// its name for a named argument or `...` for a spread.
+pub fn write(expr: &Expr, out: &mut String) {
+    out.push_str("digraph expr {\n");
+    out.push_str("    node [shape=box];\n");
+    let mut dot = Dot { out, nodes: 0 };
+    dot.expr(expr);
+    out.push_str("}\n");
+}
+
+struct Dot<'out> {
+    out: &'out mut String,
+    nodes: usize,
+}
+
+impl<'out> Dot<'out> {
+    fn node(&mut self, label: &str) -> usize {
+        let id = self.nodes;
+        self.nodes += 1;
+        writeln!(self.out, "    n{id} [label=\"{}\"];", escape(label)).unwrap();
+        id
+    }
+
+    fn edge(&mut self, from: usize, to: usize, label: Option<&str>) {
+        match label {
+            Some(label) => {
+                writeln!(self.out, "    n{from} -> n{to} [label=\"{}\"];", escape(label)).unwrap()
+            }
+            None => writeln!(self.out, "    n{from} -> n{to};").unwrap(),
+        }
+    }
+
+    fn child(&mut self, parent: usize, expr: &Expr, label: Option<&str>) {
+        let id = self.expr(expr);
+        self.edge(parent, id, label);
+    }
+
+    fn expr(&mut self, expr: &Expr) -> usize {
+        match expr {
+            Expr::Name { name, .. } => self.node(name),
+            Expr::Number { literal, .. } => self.node(literal),
+            Expr::Assign { name, right, .. } => {
+                let id = self.node("=");
+                let target = self.node(name);
+                self.edge(id, target, None);
+                self.child(id, right, None);
+                id
+            }
+            Expr::FnDef {
+                name, params, body, ..
+            } => {
+                let id = self.node("=");
+                let signature = self.node(&format!("{name}({})", params.join(", ")));
+                self.edge(id, signature, None);
+                self.child(id, body, None);
+                id
+            }
+            Expr::Lambda { param, body, .. } => {
+                let id = self.node("=>");
+                let param = self.node(param);
+                self.edge(id, param, None);
+                self.child(id, body, None);
+                id
+            }
+            Expr::Call { func, args, .. } => {
+                let id = self.node("call");
+                self.child(id, func, Some("func"));
+                for (i, arg) in args.iter().enumerate() {
+                    match arg {
+                        Arg::Positional(value) => self.child(id, value, Some(&i.to_string())),
+                        Arg::Named { name, value } => self.child(id, value, Some(&format!("{name}:"))),
+                        Arg::Spread(value) => self.child(id, value, Some("...")),
+                    }
+                }
+                id
+            }
+            Expr::Cond {
+                cond,
+                then_arm,
+                else_arm,
+                ..
+            } => {
+                let id = self.node("? :");
+                self.child(id, cond, Some("cond"));
+                self.child(id, then_arm, Some("then"));
+                self.child(id, else_arm, Some("else"));
+                id
+            }
+            Expr::Prefix { op, right, .. } => {
+                let id = self.node(op.punctuator().unwrap());
+                self.child(id, right, None);
+                id
+            }
+            Expr::Postfix { left, op, .. } => {
+                let id = self.node(&format!("_{}", op.punctuator().unwrap()));
+                self.child(id, left, None);
+                id
+            }
+            Expr::Infix { left, op, right, .. } => {
+                let id = self.node(op.punctuator().unwrap());
+                self.child(id, left, None);
+                self.child(id, right, None);
+                id
+            }
+            Expr::Pipe { left, right, .. } => {
+                let id = self.node("|>");
+                self.child(id, left, None);
+                self.child(id, right, None);
+                id
+            }
+            Expr::Mixfix {
+                pattern, operands, ..
+            } => {
+                let id = self.node(pattern);
+                for operand in operands {
+                    self.child(id, operand, None);
+                }
+                id
+            }
+        }
+    }
+}
+
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::BantamParser;
+
+    fn dot(source: &str) -> String {
+        let mut out = String::new();
+        write(&BantamParser::new(source).parse_expression(), &mut out);
+        out
+    }
+
+    #[test]
+    fn operators_and_names() {
+        assert_eq!(
+            dot("a = -b!"),
+            "digraph expr {
+    node [shape=box];
+    n0 [label=\"=\"];
+    n1 [label=\"a\"];
+    n0 -> n1;
+    n2 [label=\"-\"];
+    n3 [label=\"_!\"];
+    n4 [label=\"b\"];
+    n3 -> n4;
+    n2 -> n3;
+    n0 -> n2;
+}
+"
+        );
+    }
+
+    #[test]
+    fn labeled_edges() {
+        let out = dot("c ? f(x, ...xs, k: 1) : e");
+        assert!(out.contains("n0 [label=\"? :\"];"));
+        assert!(out.contains("n0 -> n1 [label=\"cond\"];"));
+        assert!(out.contains("n2 [label=\"call\"];"));
+        assert!(out.contains("n2 -> n3 [label=\"func\"];"));
+        assert!(out.contains("n2 -> n4 [label=\"0\"];"));
+        assert!(out.contains("n2 -> n5 [label=\"...\"];"));
+        assert!(out.contains("n2 -> n6 [label=\"k:\"];"));
+        assert!(out.contains("n0 -> n2 [label=\"then\"];"));
+        assert!(out.contains("n0 -> n7 [label=\"else\"];"));
+    }
+
+    #[test]
+    fn escaped_labels() {
+        assert_eq!(escape("say \"hi\\\""), "say \\\"hi\\\\\\\"");
+    }
+}
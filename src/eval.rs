@@ -0,0 +1,823 @@
+use crate::expression::{Arg, Expr, OwnedExpr};
+use crate::functions::{Arity, FunctionRegistry};
+use crate::number::Number;
+use crate::operators::{Fixity, OperatorTable};
+use crate::token::{Span, TokenType};
+use rustc_hash::FxHashMap;
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+/// What an expression evaluates to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Number(Number),
+    Function(Function),
+}
+
+/// The types of values, for operators that mean different things for
+/// different types.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ValueType {
+    Int,
+    Big,
+    Float,
+    Function,
+}
+
+impl Value {
+    pub fn typ(&self) -> ValueType {
+        match self {
+            Value::Number(Number::Int(_)) => ValueType::Int,
+            Value::Number(Number::Big(_)) => ValueType::Big,
+            Value::Number(Number::Float(_)) => ValueType::Float,
+            Value::Function(_) => ValueType::Function,
+        }
+    }
+
+    /// The number this is, or the error for operators and functions that
+    /// only take numbers.
+    pub(crate) fn as_number(&self) -> Result<&Number, String> {
+        match self {
+            Value::Number(n) => Ok(n),
+            value => Err(format!("Expected a number, found {value}.")),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Number(n) => write!(f, "{n}"),
+            Value::Function(func) => write!(f, "<function {}>", func.name),
+        }
+    }
+}
+
+impl From<Number> for Value {
+    fn from(n: Number) -> Self {
+        Value::Number(n)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(n: i64) -> Self {
+        Value::Number(Number::Int(n))
+    }
+}
+
+impl From<f64> for Value {
+    fn from(x: f64) -> Self {
+        Value::Number(Number::Float(x))
+    }
+}
+
+pub(crate) type Native = dyn Fn(&[Value]) -> Result<Value, String>;
+
+/// A function implemented in Rust, or defined by `f(x) = body` or
+/// `x => body`. Rust functions report errors as a message, which `eval`
+/// turns into an `EvalError` pointing at the call.
+#[derive(Clone)]
+pub struct Function {
+    pub name: String,
+    pub arity: Arity,
+    body: Body,
+}
+
+#[derive(Clone)]
+enum Body {
+    Native(Rc<Native>),
+    Defined(Rc<Defined>),
+}
+
+// A closure over the scope it was created in, `None` for the global one.
+// Stored in a variable of that scope it keeps the scope alive as long as
+// the environment's variables are.
+struct Defined {
+    params: Vec<String>,
+    body: OwnedExpr,
+    scope: Option<Rc<Scope>>,
+}
+
+// the variables of one call, in the scope its function was created in
+#[derive(Debug)]
+struct Scope {
+    vars: RefCell<FxHashMap<String, Value>>,
+    parent: Option<Rc<Scope>>,
+}
+
+impl Function {
+    pub fn new(
+        name: &str,
+        arity: Arity,
+        native: impl Fn(&[Value]) -> Result<Value, String> + 'static,
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            arity,
+            body: Body::Native(Rc::new(native)),
+        }
+    }
+
+    // `None` for functions defined by `eval`
+    pub(crate) fn native(&self) -> Option<Rc<Native>> {
+        match &self.body {
+            Body::Native(native) => Some(native.clone()),
+            Body::Defined(_) => None,
+        }
+    }
+
+    fn defined(name: &str, params: Vec<String>, body: &Expr, scope: Option<Rc<Scope>>) -> Self {
+        Self {
+            name: name.to_string(),
+            arity: Arity::Exactly(params.len()),
+            body: Body::Defined(Rc::new(Defined {
+                params,
+                body: body.to_static(),
+                scope,
+            })),
+        }
+    }
+}
+
+impl fmt::Debug for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Function")
+            .field("name", &self.name)
+            .field("arity", &self.arity)
+            .finish()
+    }
+}
+
+// the same function, not just one with the same name
+impl PartialEq for Function {
+    fn eq(&self, other: &Self) -> bool {
+        match (&self.body, &other.body) {
+            (Body::Native(a), Body::Native(b)) => Rc::ptr_eq(a, b),
+            (Body::Defined(a), Body::Defined(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+/// How deeply calls to defined functions may nest by default before `eval`
+/// gives up on what is likely runaway recursion. See `Env::with_max_depth`.
+pub const MAX_CALL_DEPTH: usize = 10_000;
+
+// Calls to defined functions run on a new stack segment of `STACK_SIZE`
+// bytes once less than `RED_ZONE` are left, so only the depth limit bounds
+// them, not the size of the thread's stack.
+const RED_ZONE: usize = 128 * 1024;
+const STACK_SIZE: usize = 1024 * 1024;
+
+/// The variables an expression is evaluated with, what its operators
+/// mean and the functions names fall back to. Assignments store into it.
+///
+/// Variables live in scopes. Outside of calls that is the global scope. A
+/// call to a defined function or lambda runs in a new scope that holds the
+/// parameters and sits inside the scope the function was created in, so a
+/// body sees the variables around its definition, even after the call that
+/// made them has returned, and not those of its callers. An assignment
+/// updates the innermost scope that has the variable and otherwise creates
+/// it in the current one, so parameters shadow outer variables.
+#[derive(Debug, Clone)]
+pub struct Env {
+    vars: FxHashMap<String, Value>,
+    // the innermost scope, `None` outside of calls
+    scope: Option<Rc<Scope>>,
+    depth: usize,
+    max_depth: usize,
+    operators: OperatorTable,
+    functions: FunctionRegistry,
+}
+
+impl Env {
+    /// An environment without variables and with the standard operators
+    /// and functions.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_operators(operators: OperatorTable) -> Self {
+        Self {
+            vars: FxHashMap::default(),
+            scope: None,
+            depth: 0,
+            max_depth: MAX_CALL_DEPTH,
+            operators,
+            functions: FunctionRegistry::default(),
+        }
+    }
+
+    /// Let calls to defined functions nest at most `max_depth` deep rather
+    /// than `MAX_CALL_DEPTH`.
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    pub fn operators(&mut self) -> &mut OperatorTable {
+        &mut self.operators
+    }
+
+    pub fn functions(&mut self) -> &mut FunctionRegistry {
+        &mut self.functions
+    }
+
+    /// The global variable `name`.
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.vars.get(name)
+    }
+
+    /// Set the global variable `name`.
+    pub fn set(&mut self, name: &str, value: Value) {
+        self.vars.insert(name.to_string(), value);
+    }
+
+    /// The variable `name` as the current scope sees it, or else the
+    /// registered function `name`.
+    pub fn lookup(&self, name: &str) -> Option<Value> {
+        for scope in self.scopes() {
+            if let Some(value) = scope.vars.borrow().get(name) {
+                return Some(value.clone());
+            }
+        }
+        match self.vars.get(name) {
+            Some(value) => Some(value.clone()),
+            None => self.functions.get(name).cloned().map(Value::Function),
+        }
+    }
+
+    // `name = value` in the current scope
+    pub(crate) fn assign(&mut self, name: &str, value: Value) {
+        for scope in self.scopes() {
+            if let Some(var) = scope.vars.borrow_mut().get_mut(name) {
+                *var = value;
+                return;
+            }
+        }
+        match &self.scope {
+            Some(scope) if !self.vars.contains_key(name) => {
+                scope.vars.borrow_mut().insert(name.to_string(), value);
+            }
+            _ => self.set(name, value),
+        }
+    }
+
+    // the local scopes, innermost first
+    fn scopes(&self) -> impl Iterator<Item = &Scope> {
+        std::iter::successors(self.scope.as_deref(), |scope| scope.parent.as_deref())
+    }
+
+    /// Bind the variable `name` to a function implemented in Rust.
+    pub fn define(
+        &mut self,
+        name: &str,
+        arity: Arity,
+        native: impl Fn(&[Value]) -> Result<Value, String> + 'static,
+    ) {
+        self.set(name, Value::Function(Function::new(name, arity, native)));
+    }
+}
+
+impl Default for Env {
+    fn default() -> Self {
+        Self::with_operators(OperatorTable::default())
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct EvalError {
+    /// The node that couldn't be evaluated.
+    pub span: Span,
+    pub message: String,
+}
+
+impl EvalError {
+    pub(crate) fn new(span: Span, message: impl Into<String>) -> Self {
+        Self {
+            span,
+            message: message.into(),
+        }
+    }
+}
+
+/// Evaluate `expr` with the variables in `env`.
+///
+/// Names are variables, or else functions of the environment's
+/// `FunctionRegistry`. `f(x) = body` assigns a function to `f`, which may
+/// call itself, and `x => body` evaluates to a function without a name;
+/// both are closures, see `Env`. Only the taken arm of a conditional is evaluated; the
+/// condition is true unless it is zero. `a |> f(b)` calls `f(a, b)`.
+/// Prefix and postfix `++` and `--` update a variable and evaluate to its
+/// new and old value; the other operators mean what the environment's
+/// `OperatorTable` says.
+/// Operators missing from it and mixfix expressions are errors, and so are
+/// calls nested deeper than the environment allows.
+pub fn eval(expr: &Expr, env: &mut Env) -> Result<Value, EvalError> {
+    match expr {
+        Expr::Name { name, span } => variable(name, env, *span),
+        Expr::Number { literal, span } => Number::parse(literal)
+            .map(Value::Number)
+            .ok_or_else(|| EvalError::new(*span, format!("Invalid number `{literal}`."))),
+        Expr::Assign { name, right, .. } => {
+            let value = eval(right, env)?;
+            env.assign(name, value.clone());
+            Ok(value)
+        }
+        Expr::FnDef {
+            name, params, body, ..
+        } => {
+            let params = params.iter().map(|param| param.to_string()).collect();
+            let func = Function::defined(name, params, body, env.scope.clone());
+            env.assign(name, Value::Function(func.clone()));
+            Ok(Value::Function(func))
+        }
+        Expr::Lambda { param, body, .. } => {
+            let params = vec![param.to_string()];
+            let func = Function::defined("lambda", params, body, env.scope.clone());
+            Ok(Value::Function(func))
+        }
+        Expr::Call { func, args, span } => {
+            let func = callee(func, env, *span)?;
+            let args = eval_args(args, env)?;
+            call(&func, &args, env, *span)
+        }
+        Expr::Cond {
+            cond,
+            then_arm,
+            else_arm,
+            ..
+        } => {
+            if !number(cond, env)?.is_zero() {
+                eval(then_arm, env)
+            } else {
+                eval(else_arm, env)
+            }
+        }
+        Expr::Prefix { op, right, span } => match op {
+            TokenType::PlusPlus | TokenType::MinusMinus => {
+                let (_, new) = increment(right, *op, env, *span)?;
+                Ok(Value::Number(new))
+            }
+            _ => {
+                let right = eval(right, env)?;
+                unary(*op, Fixity::Prefix, &right, env, *span)
+            }
+        },
+        Expr::Postfix { left, op, span } => match op {
+            TokenType::PlusPlus | TokenType::MinusMinus => {
+                let (old, _) = increment(left, *op, env, *span)?;
+                Ok(Value::Number(old))
+            }
+            _ => {
+                let left = eval(left, env)?;
+                unary(*op, Fixity::Postfix, &left, env, *span)
+            }
+        },
+        Expr::Infix {
+            left,
+            op,
+            right,
+            span,
+        } => {
+            let (left, right) = (eval(left, env)?, eval(right, env)?);
+            infix(*op, &left, &right, env, *span)
+        }
+        Expr::Pipe { left, right, span } => {
+            let left = eval(left, env)?;
+            let (func, mut args) = match &**right {
+                Expr::Call { func, args, .. } => (callee(func, env, *span)?, eval_args(args, env)?),
+                right => (callee(right, env, *span)?, vec![]),
+            };
+            args.insert(0, left);
+            call(&func, &args, env, *span)
+        }
+        Expr::Mixfix { pattern, span, .. } => Err(EvalError::new(
+            *span,
+            format!("Mixfix `{pattern}` has no meaning to evaluate."),
+        )),
+    }
+}
+
+fn number(expr: &Expr, env: &mut Env) -> Result<Number, EvalError> {
+    let value = eval(expr, env)?;
+    expect_number(value, expr.span())
+}
+
+// The steps of `eval` that the bytecode VM shares, so that it fails with the
+// same errors.
+
+pub(crate) fn variable(name: &str, env: &Env, span: Span) -> Result<Value, EvalError> {
+    env.lookup(name)
+        .ok_or_else(|| EvalError::new(span, format!("Undefined variable `{name}`.")))
+}
+
+pub(crate) fn expect_number(value: Value, span: Span) -> Result<Number, EvalError> {
+    match value {
+        Value::Number(n) => Ok(n),
+        value => Err(EvalError::new(span, value.as_number().unwrap_err())),
+    }
+}
+
+fn eval_args(args: &[Arg], env: &mut Env) -> Result<Vec<Value>, EvalError> {
+    args.iter()
+        .map(|arg| match arg {
+            Arg::Positional(value) => eval(value, env),
+            Arg::Named { name, value } => Err(EvalError::new(
+                value.span(),
+                format!("Named argument `{name}` isn't supported."),
+            )),
+            Arg::Spread(value) => Err(EvalError::new(
+                value.span(),
+                "Spread arguments aren't supported.",
+            )),
+        })
+        .collect()
+}
+
+// what `func` calls, with errors about a named callee pointing at the call
+fn callee(func: &Expr, env: &mut Env, span: Span) -> Result<Value, EvalError> {
+    match func {
+        Expr::Name { name, .. } => named_callee(name, env, span),
+        func => eval(func, env),
+    }
+}
+
+pub(crate) fn named_callee(name: &str, env: &Env, span: Span) -> Result<Value, EvalError> {
+    match env.lookup(name) {
+        Some(Value::Function(func)) => Ok(Value::Function(func)),
+        Some(value) => Err(EvalError::new(
+            span,
+            format!("`{name}` is {value}, not a function."),
+        )),
+        None => Err(EvalError::new(
+            span,
+            format!("Undefined function `{name}`."),
+        )),
+    }
+}
+
+pub(crate) fn call(
+    func: &Value,
+    args: &[Value],
+    env: &mut Env,
+    span: Span,
+) -> Result<Value, EvalError> {
+    let Value::Function(func) = func else {
+        return Err(EvalError::new(span, format!("{func} is not a function.")));
+    };
+    if !func.arity.accepts(args.len()) {
+        return Err(EvalError::new(
+            span,
+            format!(
+                "`{}` takes {}, but was given {}.",
+                func.name,
+                func.arity,
+                args.len()
+            ),
+        ));
+    }
+    match &func.body {
+        Body::Native(native) => native(args).map_err(|message| EvalError::new(span, message)),
+        Body::Defined(defined) => {
+            if env.depth == env.max_depth {
+                return Err(EvalError::new(
+                    span,
+                    format!("Calls are nested more than {} deep.", env.max_depth),
+                ));
+            }
+            let vars = defined.params.iter().cloned().zip(args.iter().cloned());
+            let scope = Rc::new(Scope {
+                vars: RefCell::new(vars.collect()),
+                parent: defined.scope.clone(),
+            });
+            let caller = env.scope.replace(scope);
+            env.depth += 1;
+            let result = stacker::maybe_grow(RED_ZONE, STACK_SIZE, || eval(&defined.body, env));
+            env.depth -= 1;
+            env.scope = caller;
+            result
+        }
+    }
+}
+
+// `++` or `--` on the variable `target`, returning its old and new value
+fn increment(
+    target: &Expr,
+    op: TokenType,
+    env: &mut Env,
+    span: Span,
+) -> Result<(Number, Number), EvalError> {
+    let Expr::Name { name, .. } = target else {
+        return Err(EvalError::new(
+            span,
+            format!("`{}` needs a variable.", op.punctuator().unwrap()),
+        ));
+    };
+    increment_variable(name, op, env, target.span())
+}
+
+// `++` or `--` on the variable `name`, with errors pointing at `span`
+pub(crate) fn increment_variable(
+    name: &str,
+    op: TokenType,
+    env: &mut Env,
+    span: Span,
+) -> Result<(Number, Number), EvalError> {
+    let old = expect_number(variable(name, env, span)?, span)?;
+    let new = if op == TokenType::PlusPlus {
+        &old + &Number::Int(1)
+    } else {
+        &old - &Number::Int(1)
+    };
+    env.assign(name, Value::Number(new.clone()));
+    Ok((old, new))
+}
+
+pub(crate) fn unary(
+    op: TokenType,
+    fixity: Fixity,
+    operand: &Value,
+    env: &Env,
+    span: Span,
+) -> Result<Value, EvalError> {
+    let result = env.operators.apply_unary(op, fixity, operand);
+    let fixity = match fixity {
+        Fixity::Prefix => "prefix",
+        Fixity::Postfix => "postfix",
+    };
+    operator_result(result, op, fixity, span)
+}
+
+pub(crate) fn infix(
+    op: TokenType,
+    left: &Value,
+    right: &Value,
+    env: &Env,
+    span: Span,
+) -> Result<Value, EvalError> {
+    let result = env.operators.apply_infix(op, left, right);
+    operator_result(result, op, "infix", span)
+}
+
+fn operator_result(
+    result: Option<Result<Value, String>>,
+    op: TokenType,
+    fixity: &str,
+    span: Span,
+) -> Result<Value, EvalError> {
+    match result {
+        Some(result) => result.map_err(|message| EvalError::new(span, message)),
+        None => Err(EvalError::new(
+            span,
+            format!(
+                "The {fixity} operator `{}` is not defined.",
+                op.punctuator().unwrap()
+            ),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::BantamParser;
+
+    fn run(source: &str, env: &mut Env) -> Result<Value, EvalError> {
+        eval(&BantamParser::new(source).parse_expression(), env)
+    }
+
+    fn value(source: &str) -> String {
+        run(source, &mut Env::new()).unwrap().to_string()
+    }
+
+    fn value_in(source: &str, env: &mut Env) -> String {
+        run(source, env).unwrap().to_string()
+    }
+
+    fn error(source: &str, env: &mut Env) -> EvalError {
+        run(source, env).expect_err("expected an error")
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(value("1 + 2 * 3 - 4 / 8"), "6.5");
+        assert_eq!(value("2 ^ 3 ^ 2"), "512");
+        assert_eq!(value("-(1.5) + +2"), "0.5");
+        assert_eq!(value("6 / 3 * 1.0"), "2.0");
+    }
+
+    #[test]
+    fn numeric_tower() {
+        assert_eq!(value("9223372036854775807 + 1"), "9223372036854775808");
+        assert_eq!(value("2 ^ 64 - 2 ^ 64 + 1"), "1");
+        assert_eq!(value("2 ^ 200 / 2 ^ 199"), "2");
+        assert_eq!(value("25!"), "15511210043330985984000000");
+        assert_eq!(value("30! / 29! - 30"), "0");
+        assert_eq!(
+            error("(2 ^ 70)!", &mut Env::new()).message,
+            "Factorial of 1180591620717411303424 is too large."
+        );
+        assert_eq!(value("1 / 0.0"), "inf");
+        assert_eq!(
+            error("3 + 1 / (2 - 2)", &mut Env::new()),
+            EvalError::new(Span::new(4, 15), "Division by zero.")
+        );
+        assert_eq!(
+            error("2 ^ 2 ^ 2 ^ 2 ^ 2 ^ 2", &mut Env::new()),
+            EvalError::new(Span::new(0, 21), "The result of `^` is too large.")
+        );
+        assert_eq!(
+            error("(0 - 3)!", &mut Env::new()).message,
+            "Factorial of -3 is not defined."
+        );
+    }
+
+    #[test]
+    fn variables() {
+        let mut env = Env::new();
+        assert_eq!(run("a = b = 2", &mut env), Ok(Value::from(2)));
+        assert_eq!(run("a * b + 1", &mut env), Ok(Value::from(5)));
+        assert_eq!(run("a++ + a", &mut env), Ok(Value::from(5)));
+        assert_eq!(run("--a", &mut env), Ok(Value::from(2)));
+        assert_eq!(env.get("a"), Some(&Value::from(2)));
+    }
+
+    #[test]
+    fn conditionals_evaluate_one_arm() {
+        let mut env = Env::new();
+        assert_eq!(run("1 ? a = 1 : (b = 2)", &mut env), Ok(Value::from(1)));
+        assert_eq!(run("0.0 ? c = 1 : (d = 2)", &mut env), Ok(Value::from(2)));
+        assert!(env.get("a").is_some() && env.get("b").is_none());
+        assert!(env.get("c").is_none() && env.get("d").is_some());
+        assert_eq!(run("0 ? undefined : 3", &mut env), Ok(Value::from(3)));
+    }
+
+    #[test]
+    fn calls_and_pipes() {
+        let mut env = Env::new();
+        env.define("sub", Arity::Exactly(2), |args| match args {
+            [Value::Number(a), Value::Number(b)] => Ok(Value::Number(a - b)),
+            _ => Err("sub takes two numbers.".to_string()),
+        });
+        assert_eq!(run("sub(5, 3)", &mut env), Ok(Value::from(2)));
+        assert_eq!(run("5 |> sub(3)", &mut env), Ok(Value::from(2)));
+        assert_eq!(
+            error("1 + sub(1, sub)", &mut env),
+            EvalError::new(Span::new(4, 15), "sub takes two numbers.")
+        );
+        assert_eq!(
+            error("1 + sub(1)", &mut env),
+            EvalError::new(
+                Span::new(4, 10),
+                "`sub` takes 2 arguments, but was given 1."
+            )
+        );
+        run("f = sub", &mut env).unwrap();
+        assert_eq!(
+            error("f(1, 2) + f", &mut env).message,
+            "Expected a number, found <function sub>."
+        );
+    }
+
+    #[test]
+    fn defined_functions() {
+        let mut env = Env::new();
+        run("square(x) = x * x", &mut env).unwrap();
+        assert_eq!(value_in("square(3) + square(1.5)", &mut env), "11.25");
+        assert_eq!(value_in("2 |> square |> square", &mut env), "16");
+        assert_eq!(value_in("square", &mut env), "<function square>");
+        assert_eq!(
+            error("square(1, 2)", &mut env).message,
+            "`square` takes 1 argument, but was given 2."
+        );
+    }
+
+    #[test]
+    fn recursion() {
+        let mut env = Env::new();
+        run("fact(n) = n ? n * fact(n - 1) : 1", &mut env).unwrap();
+        run(
+            "fib(n) = n - 1 ? (n ? fib(n - 1) + fib(n - 2) : 0) : 1",
+            &mut env,
+        )
+        .unwrap();
+        assert_eq!(value_in("fact(25)", &mut env), value("25!"));
+        assert_eq!(value_in("fib(20)", &mut env), "6765");
+        let mut env = env.with_max_depth(100);
+        run("down(n) = n ? down(n - 1) : 0", &mut env).unwrap();
+        assert_eq!(value_in("down(99)", &mut env), "0");
+        assert_eq!(
+            error("1 + down(100)", &mut env),
+            EvalError::new(Span::new(14, 25), "Calls are nested more than 100 deep.")
+        );
+    }
+
+    #[test]
+    fn deep_recursion_fits_the_stack() {
+        // a spawned thread has a smaller stack than the test's
+        std::thread::spawn(|| {
+            let mut env = Env::new();
+            run("fact(n) = n ? n * fact(n - 1) : 1", &mut env).unwrap();
+            run("down(n) = n ? down(n - 1) : 0", &mut env).unwrap();
+            assert_eq!(value_in("fact(100)", &mut env), value("100!"));
+            let deepest = format!("down({})", MAX_CALL_DEPTH - 1);
+            assert_eq!(value_in(&deepest, &mut env), "0");
+            assert_eq!(
+                error(&format!("down({MAX_CALL_DEPTH})"), &mut env).message,
+                format!("Calls are nested more than {MAX_CALL_DEPTH} deep.")
+            );
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn calls_have_their_own_frame() {
+        let mut env = Env::new();
+        run("x = 10", &mut env).unwrap();
+        run("f(n) = (y = n + x) * 2", &mut env).unwrap();
+        run("g() = n", &mut env).unwrap();
+        assert_eq!(value_in("f(1)", &mut env), "22");
+        assert_eq!(env.get("y"), None);
+        assert_eq!(env.get("x"), Some(&Value::from(10)));
+        // `g` can't see the `n` of `h`
+        run("h(n) = g()", &mut env).unwrap();
+        assert_eq!(
+            error("h(1)", &mut env),
+            EvalError::new(Span::new(6, 7), "Undefined variable `n`.")
+        );
+    }
+
+    #[test]
+    fn closures_capture_their_scope() {
+        let mut env = Env::new();
+        run("counter(n) = step => n = n + step", &mut env).unwrap();
+        run("c = counter(0)", &mut env).unwrap();
+        run("d = counter(100)", &mut env).unwrap();
+        assert_eq!(value_in("c(1) + c(2)", &mut env), "4");
+        assert_eq!(value_in("d(1)", &mut env), "101");
+        assert_eq!(value_in("c(0)", &mut env), "3");
+        assert_eq!(env.get("n"), None);
+    }
+
+    #[test]
+    fn currying() {
+        let mut env = Env::new();
+        run("adder(n) = x => x + n", &mut env).unwrap();
+        run("add = a => b => c => a * 100 + b * 10 + c", &mut env).unwrap();
+        assert_eq!(value_in("adder(2)(3)", &mut env), "5");
+        assert_eq!(value_in("adder(adder(1)(2))(3)", &mut env), "6");
+        assert_eq!(value_in("add(1)(2)(3)", &mut env), "123");
+        assert_eq!(value_in("(f => f(f(1)))(x => x * 10)", &mut env), "100");
+        assert_eq!(value_in("adder(1)", &mut env), "<function lambda>");
+        assert_eq!(
+            error("adder(1)(2, 3)", &mut env).message,
+            "`lambda` takes 1 argument, but was given 2."
+        );
+    }
+
+    #[test]
+    fn shadowing() {
+        let mut env = Env::new();
+        run("x = 1", &mut env).unwrap();
+        // parameters shadow globals, and assigning to one doesn't touch them
+        assert_eq!(value_in("(x => x = x + 10)(5) + x", &mut env), "16");
+        assert_eq!(env.get("x"), Some(&Value::from(1)));
+        // an inner lambda's parameter shadows the outer one's
+        assert_eq!(value_in("(x => (x => x * 2)(x + 1))(3)", &mut env), "8");
+        // bodies see the scope of their definition, not of their caller
+        run("n = 5", &mut env).unwrap();
+        run("h = x => x + n", &mut env).unwrap();
+        run("k(n) = h(0)", &mut env).unwrap();
+        assert_eq!(value_in("k(100)", &mut env), "5");
+        // ... as it is when they run
+        run("n = 6", &mut env).unwrap();
+        assert_eq!(value_in("h(0)", &mut env), "6");
+        // and assign to globals they can see
+        run("bump(by) = n = n + by", &mut env).unwrap();
+        assert_eq!(value_in("bump(4) + n", &mut env), "20");
+    }
+
+    #[test]
+    fn errors_point_at_the_node() {
+        let mut env = Env::new();
+        assert_eq!(
+            error("1 + x * 2", &mut env),
+            EvalError::new(Span::new(4, 5), "Undefined variable `x`.")
+        );
+        assert_eq!(
+            error("a = 2(3)", &mut env),
+            EvalError::new(Span::new(4, 8), "2 is not a function.")
+        );
+        assert_eq!(
+            error("1 + 2'", &mut env),
+            EvalError::new(Span::new(4, 6), "The postfix operator `'` is not defined.")
+        );
+        assert_eq!(
+            error("(3)++", &mut env),
+            EvalError::new(Span::new(0, 5), "`++` needs a variable.")
+        );
+    }
+}
@@ -1,33 +1,476 @@
-use crate::token::TokenType;
+use crate::token::{Span, TokenType};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::fmt;
+use std::hash::{Hash, Hasher};
 
+/// A parsed expression. Names and literals borrow from the source where
+/// they can, and every node records the span of source text it covers.
+///
+/// `==` compares spans too, so the same formula parsed from two places
+/// differs; `eq_ignore_spans` and `IgnoreSpans` look at the structure only.
+///
+/// With the `serde` feature the tree serializes to JSON and back; names
+/// are borrowed from the input when it has no escapes and owned otherwise.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Expr<'source> {
-    Name(&'source str),
+    Name {
+        #[cfg_attr(feature = "serde", serde(borrow))]
+        name: Cow<'source, str>,
+        span: Span,
+    },
+    Number {
+        #[cfg_attr(feature = "serde", serde(borrow))]
+        literal: Cow<'source, str>,
+        span: Span,
+    },
     Assign {
-        name: &'source str,
+        #[cfg_attr(feature = "serde", serde(borrow))]
+        name: Cow<'source, str>,
         right: Box<Expr<'source>>,
+        span: Span,
+    },
+    /// `name(params) = body`, an assignment whose left side is a call on
+    /// names only.
+    FnDef {
+        #[cfg_attr(feature = "serde", serde(borrow))]
+        name: Cow<'source, str>,
+        #[cfg_attr(feature = "serde", serde(borrow))]
+        params: Vec<Cow<'source, str>>,
+        body: Box<Expr<'source>>,
+        span: Span,
+    },
+    /// `param => body`, a function without a name.
+    Lambda {
+        #[cfg_attr(feature = "serde", serde(borrow))]
+        param: Cow<'source, str>,
+        body: Box<Expr<'source>>,
+        span: Span,
     },
     Call {
         func: Box<Expr<'source>>,
-        args: Vec<Box<Expr<'source>>>,
+        args: Vec<Arg<'source>>,
+        span: Span,
     },
     Cond {
         cond: Box<Expr<'source>>,
         then_arm: Box<Expr<'source>>,
         else_arm: Box<Expr<'source>>,
+        span: Span,
     },
     Prefix {
         op: TokenType,
         right: Box<Expr<'source>>,
+        span: Span,
     },
     Postfix {
         left: Box<Expr<'source>>,
         op: TokenType,
+        span: Span,
     },
     Infix {
         left: Box<Expr<'source>>,
         op: TokenType,
         right: Box<Expr<'source>>,
+        span: Span,
+    },
+    Pipe {
+        left: Box<Expr<'source>>,
+        right: Box<Expr<'source>>,
+        span: Span,
+    },
+    Mixfix {
+        #[cfg_attr(feature = "serde", serde(borrow))]
+        pattern: Cow<'source, str>,
+        operands: Vec<Expr<'source>>,
+        span: Span,
+    },
+}
+
+impl<'source> Expr<'source> {
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::Name { span, .. }
+            | Expr::Number { span, .. }
+            | Expr::Assign { span, .. }
+            | Expr::FnDef { span, .. }
+            | Expr::Lambda { span, .. }
+            | Expr::Call { span, .. }
+            | Expr::Cond { span, .. }
+            | Expr::Prefix { span, .. }
+            | Expr::Postfix { span, .. }
+            | Expr::Infix { span, .. }
+            | Expr::Pipe { span, .. }
+            | Expr::Mixfix { span, .. } => *span,
+        }
+    }
+
+    pub(crate) fn set_span(&mut self, to: Span) {
+        match self {
+            Expr::Name { span, .. }
+            | Expr::Number { span, .. }
+            | Expr::Assign { span, .. }
+            | Expr::FnDef { span, .. }
+            | Expr::Lambda { span, .. }
+            | Expr::Call { span, .. }
+            | Expr::Cond { span, .. }
+            | Expr::Prefix { span, .. }
+            | Expr::Postfix { span, .. }
+            | Expr::Infix { span, .. }
+            | Expr::Pipe { span, .. }
+            | Expr::Mixfix { span, .. } => *span = to,
+        }
+    }
+
+    /// Copy the tree into one that owns all its names, so it can outlive the
+    /// source it was parsed from, e.g. in a cache of parsed rules.
+    pub fn to_static(&self) -> Expr<'static> {
+        let owned = |name: &Cow<str>| Cow::Owned(name.to_string());
+        let boxed = |expr: &Expr| Box::new(expr.to_static());
+        match self {
+            Expr::Name { name, span } => Expr::Name {
+                name: owned(name),
+                span: *span,
+            },
+            Expr::Number { literal, span } => Expr::Number {
+                literal: owned(literal),
+                span: *span,
+            },
+            Expr::Assign { name, right, span } => Expr::Assign {
+                name: owned(name),
+                right: boxed(right),
+                span: *span,
+            },
+            Expr::FnDef {
+                name,
+                params,
+                body,
+                span,
+            } => Expr::FnDef {
+                name: owned(name),
+                params: params.iter().map(owned).collect(),
+                body: boxed(body),
+                span: *span,
+            },
+            Expr::Lambda { param, body, span } => Expr::Lambda {
+                param: owned(param),
+                body: boxed(body),
+                span: *span,
+            },
+            Expr::Call { func, args, span } => Expr::Call {
+                func: boxed(func),
+                args: args
+                    .iter()
+                    .map(|arg| match arg {
+                        Arg::Positional(value) => Arg::Positional(value.to_static()),
+                        Arg::Named { name, value } => Arg::Named {
+                            name: owned(name),
+                            value: value.to_static(),
+                        },
+                        Arg::Spread(value) => Arg::Spread(value.to_static()),
+                    })
+                    .collect(),
+                span: *span,
+            },
+            Expr::Cond {
+                cond,
+                then_arm,
+                else_arm,
+                span,
+            } => Expr::Cond {
+                cond: boxed(cond),
+                then_arm: boxed(then_arm),
+                else_arm: boxed(else_arm),
+                span: *span,
+            },
+            Expr::Prefix { op, right, span } => Expr::Prefix {
+                op: *op,
+                right: boxed(right),
+                span: *span,
+            },
+            Expr::Postfix { left, op, span } => Expr::Postfix {
+                left: boxed(left),
+                op: *op,
+                span: *span,
+            },
+            Expr::Infix {
+                left,
+                op,
+                right,
+                span,
+            } => Expr::Infix {
+                left: boxed(left),
+                op: *op,
+                right: boxed(right),
+                span: *span,
+            },
+            Expr::Pipe { left, right, span } => Expr::Pipe {
+                left: boxed(left),
+                right: boxed(right),
+                span: *span,
+            },
+            Expr::Mixfix {
+                pattern,
+                operands,
+                span,
+            } => Expr::Mixfix {
+                pattern: owned(pattern),
+                operands: operands.iter().map(Expr::to_static).collect(),
+                span: *span,
+            },
+        }
+    }
+
+    /// Structural equality: like `==`, but the spans don't have to match.
+    pub fn eq_ignore_spans(&self, other: &Expr) -> bool {
+        let args_eq = |a: &[Arg], b: &[Arg]| {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.eq_ignore_spans(b))
+        };
+        match (self, other) {
+            (Expr::Name { name: a, .. }, Expr::Name { name: b, .. }) => a == b,
+            (Expr::Number { literal: a, .. }, Expr::Number { literal: b, .. }) => a == b,
+            (
+                Expr::Assign { name, right, .. },
+                Expr::Assign {
+                    name: other_name,
+                    right: other_right,
+                    ..
+                },
+            ) => name == other_name && right.eq_ignore_spans(other_right),
+            (
+                Expr::FnDef {
+                    name, params, body, ..
+                },
+                Expr::FnDef {
+                    name: other_name,
+                    params: other_params,
+                    body: other_body,
+                    ..
+                },
+            ) => name == other_name && params == other_params && body.eq_ignore_spans(other_body),
+            (
+                Expr::Lambda { param, body, .. },
+                Expr::Lambda {
+                    param: other_param,
+                    body: other_body,
+                    ..
+                },
+            ) => param == other_param && body.eq_ignore_spans(other_body),
+            (
+                Expr::Call { func, args, .. },
+                Expr::Call {
+                    func: other_func,
+                    args: other_args,
+                    ..
+                },
+            ) => func.eq_ignore_spans(other_func) && args_eq(args, other_args),
+            (
+                Expr::Cond {
+                    cond,
+                    then_arm,
+                    else_arm,
+                    ..
+                },
+                Expr::Cond {
+                    cond: other_cond,
+                    then_arm: other_then,
+                    else_arm: other_else,
+                    ..
+                },
+            ) => {
+                cond.eq_ignore_spans(other_cond)
+                    && then_arm.eq_ignore_spans(other_then)
+                    && else_arm.eq_ignore_spans(other_else)
+            }
+            (
+                Expr::Prefix { op, right, .. },
+                Expr::Prefix {
+                    op: other_op,
+                    right: other_right,
+                    ..
+                },
+            ) => op == other_op && right.eq_ignore_spans(other_right),
+            (
+                Expr::Postfix { left, op, .. },
+                Expr::Postfix {
+                    left: other_left,
+                    op: other_op,
+                    ..
+                },
+            ) => op == other_op && left.eq_ignore_spans(other_left),
+            (
+                Expr::Infix { left, op, right, .. },
+                Expr::Infix {
+                    left: other_left,
+                    op: other_op,
+                    right: other_right,
+                    ..
+                },
+            ) => op == other_op && left.eq_ignore_spans(other_left) && right.eq_ignore_spans(other_right),
+            (
+                Expr::Pipe { left, right, .. },
+                Expr::Pipe {
+                    left: other_left,
+                    right: other_right,
+                    ..
+                },
+            ) => left.eq_ignore_spans(other_left) && right.eq_ignore_spans(other_right),
+            (
+                Expr::Mixfix {
+                    pattern, operands, ..
+                },
+                Expr::Mixfix {
+                    pattern: other_pattern,
+                    operands: other_operands,
+                    ..
+                },
+            ) => {
+                pattern == other_pattern
+                    && operands.len() == other_operands.len()
+                    && operands.iter().zip(other_operands).all(|(a, b)| a.eq_ignore_spans(b))
+            }
+            _ => false,
+        }
+    }
+
+    // the `Hash` counterpart of `eq_ignore_spans`
+    fn hash_ignore_spans<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Expr::Name { name, .. } => name.hash(state),
+            Expr::Number { literal, .. } => literal.hash(state),
+            Expr::Assign { name, right, .. } => {
+                name.hash(state);
+                right.hash_ignore_spans(state);
+            }
+            Expr::FnDef {
+                name, params, body, ..
+            } => {
+                name.hash(state);
+                params.hash(state);
+                body.hash_ignore_spans(state);
+            }
+            Expr::Lambda { param, body, .. } => {
+                param.hash(state);
+                body.hash_ignore_spans(state);
+            }
+            Expr::Call { func, args, .. } => {
+                func.hash_ignore_spans(state);
+                args.len().hash(state);
+                for arg in args {
+                    std::mem::discriminant(arg).hash(state);
+                    if let Arg::Named { name, .. } = arg {
+                        name.hash(state);
+                    }
+                    arg.value().hash_ignore_spans(state);
+                }
+            }
+            Expr::Cond {
+                cond,
+                then_arm,
+                else_arm,
+                ..
+            } => {
+                cond.hash_ignore_spans(state);
+                then_arm.hash_ignore_spans(state);
+                else_arm.hash_ignore_spans(state);
+            }
+            Expr::Prefix { op, right, .. } => {
+                op.hash(state);
+                right.hash_ignore_spans(state);
+            }
+            Expr::Postfix { left, op, .. } => {
+                op.hash(state);
+                left.hash_ignore_spans(state);
+            }
+            Expr::Infix { left, op, right, .. } => {
+                op.hash(state);
+                left.hash_ignore_spans(state);
+                right.hash_ignore_spans(state);
+            }
+            Expr::Pipe { left, right, .. } => {
+                left.hash_ignore_spans(state);
+                right.hash_ignore_spans(state);
+            }
+            Expr::Mixfix {
+                pattern, operands, ..
+            } => {
+                pattern.hash(state);
+                operands.len().hash(state);
+                for operand in operands {
+                    operand.hash_ignore_spans(state);
+                }
+            }
+        }
+    }
+}
+
+impl<'source> Arg<'source> {
+    pub fn eq_ignore_spans(&self, other: &Arg) -> bool {
+        match (self, other) {
+            (Arg::Positional(a), Arg::Positional(b)) | (Arg::Spread(a), Arg::Spread(b)) => {
+                a.eq_ignore_spans(b)
+            }
+            (Arg::Named { name, value }, Arg::Named { name: other_name, value: other_value }) => {
+                name == other_name && value.eq_ignore_spans(other_value)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Compares and hashes the expression by structure alone, so a set of
+/// `IgnoreSpans` holds each formula once wherever it was parsed from.
+#[derive(Debug, Copy, Clone)]
+pub struct IgnoreSpans<'a, 'source>(pub &'a Expr<'source>);
+
+impl<'a, 'source> PartialEq for IgnoreSpans<'a, 'source> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.eq_ignore_spans(other.0)
+    }
+}
+
+impl<'a, 'source> Eq for IgnoreSpans<'a, 'source> {}
+
+impl<'a, 'source> Hash for IgnoreSpans<'a, 'source> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash_ignore_spans(state);
+    }
+}
+
+/// An `Expr` that borrows nothing, see `Expr::to_static`.
+pub type OwnedExpr = Expr<'static>;
+
+/// A call argument. The value is an `Expr` in a tree, other
+/// representations such as `ExprNode` put their own handle there.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Arg<'source, E = Expr<'source>> {
+    Positional(E),
+    Named {
+        #[cfg_attr(feature = "serde", serde(borrow))]
+        name: Cow<'source, str>,
+        value: E,
     },
+    Spread(E),
+}
+
+impl<'source, E> Arg<'source, E> {
+    pub fn value(&self) -> &E {
+        match self {
+            Arg::Positional(value) | Arg::Named { value, .. } | Arg::Spread(value) => value,
+        }
+    }
+
+    pub fn map<F>(self, f: impl FnOnce(E) -> F) -> Arg<'source, F> {
+        match self {
+            Arg::Positional(value) => Arg::Positional(f(value)),
+            Arg::Named { name, value } => Arg::Named { name, value: f(value) },
+            Arg::Spread(value) => Arg::Spread(f(value)),
+        }
+    }
 }
 
 
@@ -35,22 +478,51 @@ pub trait Print {
     fn print(&self, out: &mut String);
 }
 
+/// The `Print` form, with every operator parenthesized.
+impl fmt::Display for Expr<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut out = String::new();
+        self.print(&mut out);
+        f.write_str(&out)
+    }
+}
+
 
 impl<'source> Print for Expr<'source> {
     fn print(&self, out: &mut String) {
         match self {
-            Expr::Name(name) => out.push_str(name),
-            Expr::Assign { name, right } => {
+            Expr::Name { name, .. } => out.push_str(name),
+            Expr::Number { literal, .. } => out.push_str(literal),
+            Expr::Assign { name, right, .. } => {
                 out.push('(');
                 out.push_str(name);
                 out.push_str(" = ");
                 right.print(out);
                 out.push(')');
             }
+            Expr::FnDef {
+                name, params, body, ..
+            } => {
+                out.push('(');
+                out.push_str(name);
+                out.push('(');
+                out.push_str(&params.join(", "));
+                out.push_str(") = ");
+                body.print(out);
+                out.push(')');
+            }
+            Expr::Lambda { param, body, .. } => {
+                out.push('(');
+                out.push_str(param);
+                out.push_str(" => ");
+                body.print(out);
+                out.push(')');
+            }
             Expr::Cond {
                 cond,
                 then_arm,
                 else_arm,
+                ..
             } => {
                 out.push('(');
                 cond.print(out);
@@ -60,41 +532,183 @@ impl<'source> Print for Expr<'source> {
                 else_arm.print(out);
                 out.push(')');
             }
-            Expr::Call { func, args } => {
+            Expr::Call { func, args, .. } => {
                 func.print(out);
                 out.push('(');
-                for (i, e) in args.iter().enumerate() {
-                    e.print(out);
+                for (i, arg) in args.iter().enumerate() {
+                    arg.print(out);
                     if i < args.len() - 1 {
                         out.push_str(", ");
                     }
                 }
                 out.push(')');
             }
-            Expr::Infix { left, op, right } => {
+            Expr::Infix { left, op, right, .. } => {
                 out.push('(');
                 left.print(out);
                 out.push(' ');
-                out.push(op.punctuator().unwrap());
+                out.push_str(op.punctuator().unwrap());
                 out.push(' ');
                 right.print(out);
                 out.push(')');
             }
-            Expr::Prefix { op, right } => {
+            Expr::Prefix { op, right, .. } => {
                 out.push('(');
-                out.push(op.punctuator().unwrap());
+                out.push_str(op.punctuator().unwrap());
                 right.print(out);
                 out.push(')');
             }
-            Expr::Postfix { left, op } => {
+            Expr::Postfix { left, op, .. } => {
+                out.push('(');
+                left.print(out);
+                out.push_str(op.punctuator().unwrap());
+                out.push(')');
+            }
+            Expr::Pipe { left, right, .. } => {
                 out.push('(');
                 left.print(out);
-                out.push(op.punctuator().unwrap());
+                out.push_str(" |> ");
+                right.print(out);
+                out.push(')');
+            }
+            Expr::Mixfix { pattern, operands, .. } => {
+                out.push('(');
+                let mut operands = operands.iter();
+                for (i, part) in pattern.split_whitespace().enumerate() {
+                    if i > 0 {
+                        out.push(' ');
+                    }
+                    match part {
+                        "_" => operands.next().unwrap().print(out),
+                        literal => out.push_str(literal),
+                    }
+                }
                 out.push(')');
             }
         }
     }
 }
 
+impl<'source> Print for Arg<'source> {
+    fn print(&self, out: &mut String) {
+        match self {
+            Arg::Positional(value) => value.print(out),
+            Arg::Named { name, value } => {
+                out.push_str(name);
+                out.push_str(": ");
+                value.print(out);
+            }
+            Arg::Spread(value) => {
+                out.push_str("...");
+                value.print(out);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "serde")]
+    use crate::lexer::Lexer;
+    use crate::parser::BantamParser;
+    #[cfg(feature = "serde")]
+    use crate::token::Token;
+    use std::collections::{HashMap, HashSet};
+
+    #[test]
+    fn derived_equality_and_hash() {
+        let expr = BantamParser::new("-a").parse_expression();
+        let expected = Expr::Prefix {
+            op: TokenType::Minus,
+            right: Box::new(Expr::Name {
+                name: Cow::Borrowed("a"),
+                span: Span::new(1, 2),
+            }),
+            span: Span::new(0, 2),
+        };
+        assert_eq!(*expr, expected);
+        assert_eq!(expr.clone(), expr);
+        assert_eq!(format!("{expr:?}").lines().count(), 1);
+
+        let sources = ["a + b", " a + b", "a + b ", "a - b"];
+        let parsed: Vec<_> = sources.iter().map(|source| BantamParser::new(source).parse_expression()).collect();
+        let exact: HashSet<&Expr> = parsed.iter().map(|expr| expr.as_ref()).collect();
+        let structural: HashSet<IgnoreSpans> = parsed.iter().map(|expr| IgnoreSpans(expr)).collect();
+        assert_eq!(exact.len(), 3);
+        assert_eq!(structural.len(), 2);
+    }
+
+    #[test]
+    fn owned_outlives_source() {
+        let mut cache: HashMap<u32, OwnedExpr> = HashMap::new();
+        for (id, rule) in [(1, "a = f(b, k: c) |> g"), (2, "x ? -y! : z"), (3, "f(x, y) = x => y")] {
+            let source = rule.to_string();
+            let expr = BantamParser::new(&source).parse_expression();
+            cache.insert(id, expr.to_static());
+        }
+        assert_eq!(cache[&1].to_string(), "(a = (f(b, k: c) |> g))");
+        assert_eq!(cache[&2].to_string(), "(x ? (-(y!)) : z)");
+        assert!(matches!(&cache[&2], Expr::Cond { span, .. } if *span == Span::new(0, 11)));
+        assert_eq!(cache[&3].to_string(), "(f(x, y) = (x => y))");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_shape() {
+        let expr = BantamParser::new("-a").parse_expression();
+        assert_eq!(
+            serde_json::to_string(&expr).unwrap(),
+            r#"{"Prefix":{"op":"Minus","right":{"Name":{"name":"a","span":{"start":1,"end":2}}},"span":{"start":0,"end":2}}}"#
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_round_trip() {
+        let source = "h(x, y) = f(a, ...b, k: c ? 1.5 : -d!) |> g";
+        let expr = BantamParser::new(source).parse_expression();
+        let json = serde_json::to_string(&expr).unwrap();
+        let back: Expr = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, *expr);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_names_borrow_or_own() {
+        let json = r#"{"Assign":{"name":"x","right":{"Name":{"name":"\u6211","span":{"start":4,"end":7}}},"span":{"start":0,"end":7}}}"#;
+        let Expr::Assign { name, right, .. } = serde_json::from_str(json).unwrap() else {
+            panic!("expected an assignment");
+        };
+        assert!(matches!(name, Cow::Borrowed("x")));
+        assert!(matches!(*right, Expr::Name { name: Cow::Owned(ref name), .. } if name == "我"));
+    }
 
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_tokens() {
+        let tokens: Vec<Token> = Lexer::new("a |> f").collect();
+        let json = serde_json::to_string(&tokens).unwrap();
+        assert_eq!(
+            json,
+            r#"[{"literal":"a","typ":"Name","span":{"start":0,"end":1}},{"literal":"|>","typ":"Pipe","span":{"start":2,"end":4}},{"literal":"f","typ":"Name","span":{"start":5,"end":6}}]"#
+        );
+        let back: Vec<Token> = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, tokens);
+    }
 
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_tokens_own_escaped_literals() {
+        let token = Token {
+            literal: Cow::Borrowed("\"a\""),
+            typ: TokenType::Name,
+            span: Span::new(0, 3),
+        };
+        let json: String = serde_json::to_string(&token).unwrap();
+        assert!(json.contains(r#""literal":"\"a\"""#));
+        let back: Token = serde_json::from_str(&json).unwrap();
+        assert!(matches!(back.literal, Cow::Owned(_)));
+        assert_eq!(back, token);
+    }
+}
@@ -0,0 +1,15 @@
+//! Mixfix forms the tests register on top of the Bantam grammar, shared by
+//! the library's tests and the binary's.
+
+use bantam_rs::parser::Mixfix;
+use bantam_rs::Precedence;
+
+pub static IF: Mixfix = Mixfix {
+    pattern: "if _ then _ else _",
+    precs: &[0, 0, 0],
+};
+
+pub static SLICE: Mixfix = Mixfix {
+    pattern: "_ [ _ .. _ ]",
+    precs: &[Precedence::Call as u8, 0, 0],
+};
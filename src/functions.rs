@@ -0,0 +1,205 @@
+use crate::eval::{Function, Value};
+use crate::number::Number;
+use rustc_hash::FxHashMap;
+use std::fmt;
+
+/// How many arguments a function takes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Arity {
+    Exactly(usize),
+    AtLeast(usize),
+}
+
+impl Arity {
+    pub fn accepts(self, count: usize) -> bool {
+        match self {
+            Arity::Exactly(n) => count == n,
+            Arity::AtLeast(n) => count >= n,
+        }
+    }
+}
+
+/// `1 argument`, `at least 2 arguments`
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (n, at_least) = match *self {
+            Arity::Exactly(n) => (n, ""),
+            Arity::AtLeast(n) => (n, "at least "),
+        };
+        let s = if n == 1 { "" } else { "s" };
+        write!(f, "{at_least}{n} argument{s}")
+    }
+}
+
+/// Functions implemented in Rust that names fall back to when no variable
+/// has them, so `sqrt(2)` calls the registered `sqrt` unless a variable
+/// `sqrt` shadows it.
+#[derive(Debug, Clone)]
+pub struct FunctionRegistry {
+    functions: FxHashMap<String, Function>,
+}
+
+impl FunctionRegistry {
+    pub fn empty() -> Self {
+        Self {
+            functions: FxHashMap::default(),
+        }
+    }
+
+    /// `sqrt`, `exp`, `ln`, `sin` and `cos` as floats, `abs`, `min` and `max`
+    /// of any number, and `round`, `floor` and `ceil` to integers.
+    pub fn standard() -> Self {
+        let mut registry = Self::empty();
+        for (name, f) in [
+            ("sqrt", f64::sqrt as fn(f64) -> f64),
+            ("exp", f64::exp),
+            ("ln", f64::ln),
+            ("sin", f64::sin),
+            ("cos", f64::cos),
+        ] {
+            registry.register(name, Arity::Exactly(1), move |args| {
+                Ok(Value::from(f(args[0].as_number()?.to_f64())))
+            });
+        }
+        for (name, round) in [
+            ("round", f64::round as fn(f64) -> f64),
+            ("floor", f64::floor),
+            ("ceil", f64::ceil),
+        ] {
+            registry.register(name, Arity::Exactly(1), move |args| {
+                args[0].as_number()?.to_integer(round).map(Value::from)
+            });
+        }
+        registry
+            .register("abs", Arity::Exactly(1), |args| {
+                Ok(Value::from(args[0].as_number()?.abs()))
+            })
+            .register("min", Arity::AtLeast(1), |args| extreme(args, |a, b| a < b))
+            .register("max", Arity::AtLeast(1), |args| extreme(args, |a, b| a > b));
+        registry
+    }
+
+    /// Register `f` as `name`. It is only called with a number of arguments
+    /// `arity` accepts.
+    pub fn register(
+        &mut self,
+        name: &str,
+        arity: Arity,
+        f: impl Fn(&[Value]) -> Result<Value, String> + 'static,
+    ) -> &mut Self {
+        self.functions
+            .insert(name.to_string(), Function::new(name, arity, f));
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Function> {
+        self.functions.get(name)
+    }
+}
+
+impl Default for FunctionRegistry {
+    fn default() -> Self {
+        Self::standard()
+    }
+}
+
+// the first argument that `better` prefers to all others
+fn extreme(args: &[Value], better: fn(&Number, &Number) -> bool) -> Result<Value, String> {
+    let mut best = args[0].as_number()?;
+    for arg in &args[1..] {
+        let arg = arg.as_number()?;
+        if better(arg, best) {
+            best = arg;
+        }
+    }
+    Ok(Value::Number(best.clone()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::{eval, Env, EvalError};
+    use crate::parser::BantamParser;
+    use crate::token::Span;
+
+    fn run(source: &str, env: &mut Env) -> Result<String, EvalError> {
+        eval(&BantamParser::new(source).parse_expression(), env).map(|value| value.to_string())
+    }
+
+    fn value(source: &str) -> String {
+        run(source, &mut Env::new()).unwrap()
+    }
+
+    fn error(source: &str) -> EvalError {
+        run(source, &mut Env::new()).unwrap_err()
+    }
+
+    #[test]
+    fn standard_functions() {
+        assert_eq!(value("sqrt(16) + abs(0 - 3)"), "7.0");
+        assert_eq!(
+            value("min(3, 1.5, 2) + max(1, 2 ^ 70)"),
+            "1.1805916207174113e21"
+        );
+        assert_eq!(value("max(2 ^ 70, 1) - 2 ^ 70"), "0");
+        assert_eq!(value("round(2.5) + floor(0 - 0.5) + ceil(0.5)"), "3");
+        assert_eq!(value("16 |> sqrt"), "4.0");
+    }
+
+    #[test]
+    fn wrong_number_of_arguments() {
+        assert_eq!(
+            error("1 + sqrt(1, 2)"),
+            EvalError {
+                span: Span::new(4, 14),
+                message: "`sqrt` takes 1 argument, but was given 2.".to_string(),
+            }
+        );
+        assert_eq!(
+            error("max()").message,
+            "`max` takes at least 1 argument, but was given 0."
+        );
+        assert_eq!(
+            error("2 |> min(1, 3) |> abs(1)").message,
+            "`abs` takes 1 argument, but was given 2."
+        );
+    }
+
+    #[test]
+    fn not_functions() {
+        assert_eq!(
+            error("x = 1 + nope(2)"),
+            EvalError {
+                span: Span::new(8, 15),
+                message: "Undefined function `nope`.".to_string(),
+            }
+        );
+        let mut env = Env::new();
+        run("sqrt = 4", &mut env).unwrap();
+        assert_eq!(
+            run("sqrt(2)", &mut env).unwrap_err(),
+            EvalError {
+                span: Span::new(0, 7),
+                message: "`sqrt` is 4, not a function.".to_string(),
+            }
+        );
+        assert_eq!(error("(1 + 2)(3)").message, "3 is not a function.");
+    }
+
+    #[test]
+    fn host_functions() {
+        let mut env = Env::new();
+        env.functions()
+            .register("hypot", Arity::Exactly(2), |args| match args {
+                [Value::Number(a), Value::Number(b)] => {
+                    Ok(Value::from(a.to_f64().hypot(b.to_f64())))
+                }
+                _ => Err("`hypot` takes numbers.".to_string()),
+            });
+        assert_eq!(run("hypot(3, 4)", &mut env), Ok("5.0".to_string()));
+        assert_eq!(
+            run("hypot(3, sqrt)", &mut env).unwrap_err().message,
+            "`hypot` takes numbers."
+        );
+    }
+}
@@ -0,0 +1,194 @@
+use crate::builder::{AstBuilder, Boxed, ExprNode};
+use crate::expression::Expr;
+use crate::token::Span;
+use rustc_hash::FxHashMap;
+use std::ops::Index;
+
+/// Index of a node in a `HashCons`. Structurally equal subtrees, spans
+/// aside, get the same id.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(u32);
+
+impl NodeId {
+    pub(crate) fn from_index(index: usize) -> Self {
+        NodeId(index as u32)
+    }
+
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// A node of a `HashCons` together with the span of this one occurrence,
+/// since equal subtrees at different places share their node.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Shared {
+    pub id: NodeId,
+    pub span: Span,
+}
+
+/// Every distinct subtree stored once, so a parse or an `Expr` becomes a DAG:
+///
+/// ```
+/// use bantam_rs::hashcons::HashCons;
+/// use bantam_rs::parser::BantamParser;
+///
+/// let mut parser = BantamParser::with_builder("f(a, b) * f(a, b)", HashCons::default());
+/// let root = parser.parse_expression();
+/// let nodes = parser.into_builder();
+/// // a, b, f, f(a, b) and the product
+/// assert_eq!(nodes.len(), 5);
+/// assert_eq!(nodes.occurrences(root.id)[3], 2);
+/// ```
+///
+/// Ids are handed out children first, so a node's children always have
+/// smaller ids than the node itself.
+#[derive(Debug, Default, Clone)]
+pub struct HashCons<'source> {
+    nodes: Vec<ExprNode<'source, NodeId>>,
+    ids: FxHashMap<ExprNode<'source, NodeId>, NodeId>,
+}
+
+impl<'source> HashCons<'source> {
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// The id of `node`, adding it unless an equal node is already there.
+    /// Its span is dropped.
+    pub fn intern(&mut self, mut node: ExprNode<'source, NodeId>) -> NodeId {
+        node.set_span(Span::default());
+        if let Some(id) = self.ids.get(&node) {
+            return *id;
+        }
+        let id = NodeId(
+            self.nodes
+                .len()
+                .try_into()
+                .expect("Too many nodes for a HashCons."),
+        );
+        self.nodes.push(node.clone());
+        self.ids.insert(node, id);
+        id
+    }
+
+    /// Intern every subtree of `expr`, returning the id of the root.
+    pub fn add(&mut self, expr: &Expr<'source>) -> NodeId {
+        self.add_with(expr, &mut |_, _| {})
+    }
+
+    /// Like `add`, calling `seen` with each subtree and its id, children
+    /// before their parents.
+    pub fn add_with<'a>(
+        &mut self,
+        expr: &'a Expr<'source>,
+        seen: &mut impl FnMut(NodeId, &'a Expr<'source>),
+    ) -> NodeId {
+        let node = ExprNode::view(expr).map(|child| self.add_with(child, seen));
+        let id = self.intern(node);
+        seen(id, expr);
+        id
+    }
+
+    /// How often each node occurs in the tree below `root` once it is
+    /// expanded again, indexed by `NodeId::index`. Nodes outside that tree
+    /// occur zero times.
+    pub fn occurrences(&self, root: NodeId) -> Vec<usize> {
+        let mut counts = vec![0; self.nodes.len()];
+        counts[root.index()] = 1;
+        // parents come after their children, so every count is final by the
+        // time it is handed down
+        for index in (0..=root.index()).rev() {
+            for child in self.nodes[index].children() {
+                counts[child.index()] += counts[index];
+            }
+        }
+        counts
+    }
+
+    /// Expand the node at `id` into a tree. Spans are not kept, so they are
+    /// all empty.
+    pub fn to_expr(&self, id: NodeId) -> Expr<'source> {
+        let node = self[id].clone().map(|child| Box::new(self.to_expr(child)));
+        *Boxed.build(node)
+    }
+}
+
+impl<'source> Index<NodeId> for HashCons<'source> {
+    type Output = ExprNode<'source, NodeId>;
+
+    fn index(&self, id: NodeId) -> &Self::Output {
+        &self.nodes[id.index()]
+    }
+}
+
+impl<'source> AstBuilder<'source> for HashCons<'source> {
+    type Node = Shared;
+
+    fn build(&mut self, node: ExprNode<'source, Shared>) -> Shared {
+        let span = node.span();
+        let id = self.intern(node.map(|child| child.id));
+        Shared { id, span }
+    }
+
+    /// Nodes may be shared, so this copies the node rather than taking it
+    /// out. Only the top level keeps its span.
+    fn take(&mut self, node: Shared) -> ExprNode<'source, Shared> {
+        let mut taken = self[node.id].clone().map(|id| Shared {
+            id,
+            span: Span::default(),
+        });
+        taken.set_span(node.span);
+        taken
+    }
+
+    fn span(&self, node: &Shared) -> Span {
+        node.span
+    }
+
+    fn set_span(&mut self, node: &mut Shared, span: Span) {
+        node.span = span;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::BantamParser;
+
+    #[test]
+    fn equal_subtrees_share_ids() {
+        let mut parser = BantamParser::with_builder("g(a + b, (a+b), a - b)", HashCons::default());
+        let root = parser.parse_expression();
+        let nodes = parser.into_builder();
+        let ExprNode::Call { args, .. } = &nodes[root.id] else {
+            panic!("expected a call");
+        };
+        assert_eq!(args[0].value(), args[1].value());
+        assert_ne!(args[0].value(), args[2].value());
+        // g, a, b, a + b, a - b and the call
+        assert_eq!(nodes.len(), 6);
+        assert_eq!(root.span, Span::new(0, 22));
+    }
+
+    #[test]
+    fn same_nodes_as_adding_a_tree() {
+        let source = "x = f(a, b) * f(a, b) + f(a, b) |> h";
+        let expr = BantamParser::new(source).parse_expression();
+        let mut nodes = HashCons::default();
+        let root = nodes.add(&expr);
+        assert!(nodes.to_expr(root).eq_ignore_spans(&expr));
+
+        let mut parser = BantamParser::with_builder(source, nodes);
+        let parsed = parser.parse_expression();
+        assert_eq!(parsed.id, root);
+        assert_eq!(parsed.span, expr.span());
+        let mut nodes = parser.into_builder();
+        let f_a_b = nodes.add(&BantamParser::new("f(a, b)").parse_expression());
+        assert_eq!(nodes.occurrences(root)[f_a_b.index()], 3);
+    }
+}
@@ -1,10 +1,12 @@
+use std::borrow::Cow;
 use crate::token::*;
 use core::str::CharIndices;
 use rustc_hash::FxHashMap;
 use strum::IntoEnumIterator;
 
 pub struct Lexer<'source> {
-    punctuators: FxHashMap<char, TokenType>,
+    // keyed by first char, longest punctuator first
+    punctuators: FxHashMap<char, Vec<(&'static str, TokenType)>>,
     input: &'source str,
     iter: CharIndices<'source>,
     c: char,
@@ -13,12 +15,16 @@ pub struct Lexer<'source> {
 
 impl<'source> Lexer<'source> {
     pub fn new(input: &'source str) -> Self {
-        let mut punctuators = FxHashMap::<char, TokenType>::default();
+        let mut punctuators = FxHashMap::<char, Vec<(&'static str, TokenType)>>::default();
         for tt in TokenType::iter() {
             if let Some(punc) = tt.punctuator() {
-                punctuators.insert(punc, tt);
+                let first = punc.chars().next().unwrap();
+                punctuators.entry(first).or_default().push((punc, tt));
             }
         }
+        for candidates in punctuators.values_mut() {
+            candidates.sort_by_key(|(punc, _)| std::cmp::Reverse(punc.len()));
+        }
 
         let mut lex = Self {
             iter: input.char_indices(),
@@ -37,33 +43,84 @@ impl<'source> Lexer<'source> {
             self.skip_chars();
             if self.is_at_end() {
                 break Token {
-                    literal: "",
+                    literal: Cow::Borrowed(""),
                     typ: TokenType::Eof,
+                    span: Span::new(self.ci, self.ci),
                 };
             }
-            if let Some(&tt) = self.punctuators.get(&self.c) {
-                let tok = Token {
-                    typ: tt,
-                    literal: &self.input[self.ci..self.ci + 1],
-                };
-                self.scan_char();
+            if let Some(tok) = self.scan_punctuator() {
                 break tok;
             } else if self.c.is_alphabetic() {
                 break self.scan_name();
+            } else if self.c == '$' && self.input[self.ci + 1..].starts_with(char::is_alphabetic) {
+                break self.scan_metavar();
+            } else if self.c.is_ascii_digit() {
+                break self.scan_number();
             } else {
                 self.scan_char();
             }
         }
     }
 
+    fn scan_punctuator(&mut self) -> Option<Token<'source>> {
+        let rest = &self.input[self.ci..];
+        let &(punc, typ) = self
+            .punctuators
+            .get(&self.c)?
+            .iter()
+            .find(|(punc, _)| rest.starts_with(punc))?;
+        let tok = Token {
+            typ,
+            literal: Cow::Borrowed(&rest[..punc.len()]),
+            span: Span::new(self.ci, self.ci + punc.len()),
+        };
+        for _ in punc.chars() {
+            self.scan_char();
+        }
+        Some(tok)
+    }
+
     fn scan_name(&mut self) -> Token<'source> {
         let start = self.ci;
         while self.c.is_alphabetic() {
             self.scan_char();
         }
         Token {
-            literal: &self.input[start..self.ci],
+            literal: Cow::Borrowed(&self.input[start..self.ci]),
             typ: TokenType::Name,
+            span: Span::new(start, self.ci),
+        }
+    }
+
+    // `$` and a name, the placeholders of rewrite rules
+    fn scan_metavar(&mut self) -> Token<'source> {
+        let start = self.ci;
+        self.scan_char();
+        self.scan_name();
+        Token {
+            literal: Cow::Borrowed(&self.input[start..self.ci]),
+            typ: TokenType::Metavar,
+            span: Span::new(start, self.ci),
+        }
+    }
+
+    // digits with an optional fraction, `1..2` stays a range
+    fn scan_number(&mut self) -> Token<'source> {
+        let start = self.ci;
+        while self.c.is_ascii_digit() {
+            self.scan_char();
+        }
+        let rest = &self.input[self.ci..];
+        if rest.starts_with('.') && rest[1..].starts_with(|c: char| c.is_ascii_digit()) {
+            self.scan_char();
+            while self.c.is_ascii_digit() {
+                self.scan_char();
+            }
+        }
+        Token {
+            literal: Cow::Borrowed(&self.input[start..self.ci]),
+            typ: TokenType::Number,
+            span: Span::new(start, self.ci),
         }
     }
 
@@ -106,16 +163,18 @@ mod tests {
 
     macro_rules! assert_tok {
         ($tok:expr, $wantype:expr, $wantval:expr) => {
+            let tok = $tok.clone();
             assert_eq!(
                 $tok,
                 Token {
                     typ: $wantype,
-                    literal: $wantval
+                    literal: Cow::Borrowed($wantval),
+                    ..tok
                 }
             );
         };
         ($tok:expr, $wantype:expr) => {
-            let tok = $tok;
+            let tok = $tok.clone();
             assert_eq!(
                 $tok,
                 Token {
@@ -164,11 +223,28 @@ mod tests {
     fn ignore_other_chars() {
         let lex = Lexer::new("c123 = a[] + bb{}f");
         let toks: Vec<Token> = lex.collect();
-        assert_eq!(toks.len(), 6);
+        assert_eq!(toks.len(), 9);
         assert_tok!(toks[0], TokenType::Name, "c");
-        assert_tok!(toks[2], TokenType::Name, "a");
-        assert_tok!(toks[4], TokenType::Name, "bb");
-        assert_tok!(toks[5], TokenType::Name, "f");
+        assert_tok!(toks[1], TokenType::Number, "123");
+        assert_tok!(toks[3], TokenType::Name, "a");
+        assert_tok!(toks[4], TokenType::LeftBracket);
+        assert_tok!(toks[5], TokenType::RightBracket);
+        assert_tok!(toks[7], TokenType::Name, "bb");
+        assert_tok!(toks[8], TokenType::Name, "f");
+    }
+
+    #[test]
+    fn numbers() {
+        let lex = Lexer::new("2x + 3.25 * 1..20 - 4.");
+        let toks: Vec<Token> = lex.collect();
+        assert_eq!(toks.len(), 10);
+        assert_tok!(toks[0], TokenType::Number, "2");
+        assert_tok!(toks[1], TokenType::Name, "x");
+        assert_tok!(toks[3], TokenType::Number, "3.25");
+        assert_tok!(toks[5], TokenType::Number, "1");
+        assert_tok!(toks[6], TokenType::DotDot);
+        assert_tok!(toks[7], TokenType::Number, "20");
+        assert_tok!(toks[9], TokenType::Number, "4");
     }
 
     #[test]
@@ -182,6 +258,46 @@ mod tests {
         assert_tok!(toks[12], TokenType::Name, "g");
     }
 
+    #[test]
+    fn multi_char_punctuator() {
+        let lex = Lexer::new("a |> f(b) | c > d");
+        let toks: Vec<Token> = lex.collect();
+        assert_eq!(toks.len(), 8);
+        assert_tok!(toks[1], TokenType::Pipe, "|>");
+        assert_tok!(toks[2], TokenType::Name, "f");
+        assert_tok!(toks[6], TokenType::Name, "c");
+        assert_tok!(toks[7], TokenType::Name, "d");
+
+        let lex = Lexer::new("a[b..c] . d(...e)");
+        let toks: Vec<Token> = lex.collect();
+        assert_eq!(toks.len(), 11);
+        assert_tok!(toks[3], TokenType::DotDot, "..");
+        assert_tok!(toks[6], TokenType::Name, "d");
+        assert_tok!(toks[8], TokenType::Ellipsis, "...");
+
+        let lex = Lexer::new("a+++b-- - -c'");
+        let toks: Vec<Token> = lex.collect();
+        assert_eq!(toks.len(), 9);
+        assert_tok!(toks[1], TokenType::PlusPlus, "++");
+        assert_tok!(toks[2], TokenType::Plus, "+");
+        assert_tok!(toks[4], TokenType::MinusMinus, "--");
+        assert_tok!(toks[5], TokenType::Minus, "-");
+        assert_tok!(toks[6], TokenType::Minus, "-");
+        assert_tok!(toks[8], TokenType::Apostrophe, "'");
+    }
+
+    #[test]
+    fn metavariables() {
+        let lex = Lexer::new("$x + $ y => $我");
+        let toks: Vec<Token> = lex.collect();
+        assert_eq!(toks.len(), 5);
+        assert_tok!(toks[0], TokenType::Metavar, "$x");
+        assert_eq!(toks[0].span, Span::new(0, 2));
+        assert_tok!(toks[2], TokenType::Name, "y");
+        assert_tok!(toks[3], TokenType::FatArrow, "=>");
+        assert_tok!(toks[4], TokenType::Metavar, "$我");
+    }
+
     #[test]
     fn return_multi_eof() {
         let mut lex = Lexer::new("a=b+c");
@@ -190,6 +306,13 @@ mod tests {
             toks.push(lex.next_token());
         }
         assert_eq!(toks.len(), 16);
-        toks.iter().skip(5).all(|tok| tok == &Token{typ: TokenType::Eof, literal: ""});
+        assert!(toks.iter().skip(5).all(|tok| tok == &Token{typ: TokenType::Eof, literal: Cow::Borrowed(""), span: Span::new(5, 5)}));
+    }
+
+    #[test]
+    fn spans() {
+        let lex = Lexer::new(" ab |> 12.5 我");
+        let spans: Vec<Span> = lex.map(|tok| tok.span).collect();
+        assert_eq!(spans, [Span::new(1, 3), Span::new(4, 6), Span::new(7, 11), Span::new(12, 15)]);
     }
 }
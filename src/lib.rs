@@ -0,0 +1,43 @@
+pub mod arena;
+pub mod bigint;
+pub mod ast_diff;
+pub mod builder;
+pub mod bytecode;
+pub mod compiled;
+pub mod cse;
+pub mod derivative;
+pub mod dot;
+pub mod eval;
+pub mod expression;
+pub mod functions;
+pub mod hashcons;
+pub mod lexer;
+pub mod number;
+pub mod operators;
+pub mod parselets;
+pub mod parser;
+pub mod pretty;
+pub mod rewrite;
+pub mod sexpr;
+pub mod simplify;
+pub mod token;
+pub mod visit;
+
+// so the test fixtures shared with the binary can name this crate like it does
+#[cfg(test)]
+extern crate self as bantam_rs;
+#[cfg(test)]
+mod fixtures;
+
+#[repr(u8)]
+pub enum Precedence {
+    Assignment = 1,
+    Pipe,
+    Conditional,
+    Sum,
+    Product,
+    Exponent,
+    Prefix,
+    Postfix,
+    Call,
+}
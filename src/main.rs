@@ -1,35 +1,100 @@
-mod expression;
-mod lexer;
-mod parselets;
-mod parser;
-mod token;
-
-use crate::{expression::Print, parser::BantamParser};
-
-#[repr(u8)]
-enum Precedence {
-    Assignment = 1,
-    Conditional,
-    Sum,
-    Product,
-    Exponent,
-    Prefix,
-    Postfix,
-    Call,
+use bantam_rs::bytecode::compile;
+use bantam_rs::eval::{eval, Env, EvalError};
+use bantam_rs::{dot, expression::Print, parser::BantamParser, pretty::Pretty, sexpr};
+
+const USAGE: &str = "usage: bantam_rs [--print | --pretty | --sexpr | --dot | --eval | --bytecode] [expression]
+
+  --print     print the expression with every operator parenthesized (the default)
+  --pretty    print the expression with only the parentheses it needs
+  --sexpr     print the expression as an s-expression
+  --dot       print the syntax tree as a Graphviz graph
+  --eval      evaluate the expression
+  --bytecode  print the bytecode of the expression; function definitions and
+              lambdas can't be compiled, and calls of defined functions are
+              evaluated by walking their tree";
+
+enum Format {
+    Print,
+    Pretty,
+    Sexpr,
+    Dot,
+    Eval,
+    Bytecode,
 }
 
 fn main() {
-    let mut parser = BantamParser::new("a = b + c * d ^ e - f / g");
+    let mut format = Format::Print;
+    let mut source = None;
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--print" => format = Format::Print,
+            "--pretty" => format = Format::Pretty,
+            "--sexpr" => format = Format::Sexpr,
+            "--dot" => format = Format::Dot,
+            "--eval" => format = Format::Eval,
+            "--bytecode" => format = Format::Bytecode,
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return;
+            }
+            flag if flag.starts_with("--") => {
+                eprintln!("unknown option {flag}\n{USAGE}");
+                std::process::exit(2);
+            }
+            _ => source = Some(arg),
+        }
+    }
+    let source = source.unwrap_or_else(|| "a = b + c * d ^ e - f / g".to_string());
+
+    let mut parser = BantamParser::new(&source);
     let expr = parser.parse_expression();
     let mut out = String::new();
-    expr.print(&mut out);
+    match format {
+        Format::Print => {
+            expr.print(&mut out);
+            println!("ast string: {out}");
+        }
+        Format::Pretty => {
+            Pretty::new(&BantamParser::grammar()).print(&expr, &mut out);
+            println!("{out}");
+        }
+        Format::Sexpr => {
+            sexpr::write(&expr, &mut out);
+            println!("{out}");
+        }
+        Format::Dot => {
+            dot::write(&expr, &mut out);
+            print!("{out}");
+        }
+        Format::Eval => match eval(&expr, &mut Env::new()) {
+            Ok(value) => println!("{value}"),
+            Err(error) => fail(error),
+        },
+        Format::Bytecode => match compile(&expr) {
+            Ok(chunk) => print!("{chunk}"),
+            Err(error) => fail(error),
+        },
+    }
+}
 
-    println!("ast string: {out}");
+fn fail(error: EvalError) -> ! {
+    let span = error.span;
+    eprintln!("error at {}..{}: {}", span.start, span.end, error.message);
+    std::process::exit(1);
 }
 
+#[cfg(test)]
+#[path = "fixtures.rs"]
+mod fixtures;
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::fixtures::{IF, SLICE};
+    use bantam_rs::expression::Expr;
+    use bantam_rs::parser::Mixfix;
+    use bantam_rs::visit::Visitor;
+    use bantam_rs::Precedence;
 
     fn test_helper(source: &'static str, expected: &'static str) -> bool {
         let mut parser = BantamParser::new(source);
@@ -39,6 +104,68 @@ mod tests {
         out == expected
     }
 
+    fn juxtaposition_helper(apply: bool, source: &'static str, expected: &'static str) -> bool {
+        let mut parser = BantamParser::new(source);
+        if apply {
+            parser.application_by_adjacency();
+        } else {
+            parser.implicit_multiplication();
+        }
+        let expr = parser.parse_expression();
+        let mut out = String::new();
+        expr.print(&mut out);
+        out == expected
+    }
+
+    fn mixfix_helper(mixfixes: &[&'static Mixfix], source: &'static str, expected: &'static str) -> bool {
+        let mut parser = BantamParser::new(source);
+        for mixfix in mixfixes {
+            parser.register_mixfix(mixfix);
+        }
+        let expr = parser.parse_expression();
+        let mut out = String::new();
+        expr.print(&mut out);
+        out == expected
+    }
+
+    // the source text covered by every node, in pre-order
+    struct Spans<'source> {
+        source: &'source str,
+        texts: Vec<&'source str>,
+    }
+
+    impl<'source> Visitor<'source> for Spans<'source> {
+        fn enter(&mut self, expr: &Expr<'source>) {
+            let span = expr.span();
+            self.texts.push(&self.source[span.start..span.end]);
+        }
+    }
+
+    fn spans_helper(mixfixes: &[&'static Mixfix], source: &'static str) -> Vec<&'static str> {
+        let mut parser = BantamParser::new(source);
+        for mixfix in mixfixes {
+            parser.register_mixfix(mixfix);
+        }
+        let expr = parser.parse_expression();
+        let mut spans = Spans { source, texts: vec![] };
+        spans.visit_expr(&expr);
+        spans.texts
+    }
+
+    static COND: Mixfix = Mixfix {
+        pattern: "_ ? _ : _",
+        precs: &[Precedence::Conditional as u8, 0, Precedence::Conditional as u8 - 1],
+    };
+
+    fn desugar_helper(source: &'static str, expected: &'static str) -> bool {
+        let mut parser = BantamParser::new(source);
+        parser.desugar_pipes();
+        let expr = parser.parse_expression();
+        let mut out = String::new();
+        expr.print(&mut out);
+        out == expected
+    }
+
     #[test]
     fn unary() {
         assert!(test_helper("~ ! - + a", "(~(!(-(+a))))"));
@@ -54,6 +181,36 @@ mod tests {
         assert!(test_helper("! a !", "(!(a!))"));
     }
 
+    #[test]
+    fn increment_decrement() {
+        assert!(test_helper("++a", "(++a)"));
+        assert!(test_helper("a++", "(a++)"));
+        assert!(test_helper("--a--", "(--(a--))"));
+        assert!(test_helper("a-- + b++", "((a--) + (b++))"));
+        assert!(test_helper("- a --", "(-(a--))"));
+        assert!(test_helper("f(a)++", "(f(a)++)"));
+    }
+
+    #[test]
+    fn transpose() {
+        assert!(test_helper("a'", "(a')"));
+        assert!(test_helper("a' * b'", "((a') * (b'))"));
+        assert!(test_helper("a'' ^ b", "(((a')') ^ b)"));
+    }
+
+    #[test]
+    fn try_or_conditional() {
+        assert!(test_helper("a?", "(a?)"));
+        assert!(test_helper("a? * b", "((a?) * b)"));
+        assert!(test_helper("f(a?, b)", "f((a?), b)"));
+        assert!(test_helper("a'?", "((a')?)"));
+        assert!(test_helper("a ?? b : c", "((a?) ? b : c)"));
+        assert!(test_helper("a ? -b : c", "(a ? (-b) : c)"));
+        // `+` can start an expression, so this `?` is a conditional
+        assert!(test_helper("a? + b : c", "(a ? (+b) : c)"));
+        assert!(test_helper("(a?) + b", "((a?) + b)"));
+    }
+
     #[test]
     fn binary() {
         assert!(test_helper(
@@ -90,10 +247,196 @@ mod tests {
         assert!(test_helper("a(b?c:d,e+f)", "a((b ? c : d), (e + f))"));
     }
 
+    #[test]
+    fn func_call_args() {
+        assert!(test_helper("a(b,)", "a(b)"));
+        assert!(test_helper("a(b, c,)", "a(b, c)"));
+        assert!(test_helper("a(b, c: d)", "a(b, c: d)"));
+        assert!(test_helper("a(b: c ? d : e, f: g + h)", "a(b: (c ? d : e), f: (g + h))"));
+        assert!(test_helper("a(...b)", "a(...b)"));
+        assert!(test_helper("a(b, ...c(d), e: f,)", "a(b, ...c(d), e: f)"));
+        assert!(test_helper("a(b ? c : d)", "a((b ? c : d))"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Positional argument after named argument.")]
+    fn func_call_positional_after_named() {
+        test_helper("a(b: c, d)", "");
+    }
+
+    #[test]
+    #[should_panic(expected = "Positional argument after named argument.")]
+    fn func_call_spread_after_named() {
+        test_helper("a(b: c, ...d)", "");
+    }
+
+    #[test]
+    #[should_panic(expected = "Duplicate named argument \"b\".")]
+    fn func_call_duplicate_named() {
+        test_helper("a(b: c, d: e, b: f)", "");
+    }
+
+    #[test]
+    #[should_panic(expected = "Could not parse")]
+    fn func_call_lone_comma() {
+        test_helper("a(,)", "");
+    }
+
+    #[test]
+    fn function_definition() {
+        assert!(test_helper("f(x) = x * x", "(f(x) = (x * x))"));
+        assert!(test_helper("f() = g(a, b) = a + b", "(f() = (g(a, b) = (a + b)))"));
+        assert!(test_helper("f(x,) = x ? 1 : 2", "(f(x) = (x ? 1 : 2))"));
+    }
+
+    #[test]
+    #[should_panic(expected = "The parameters of a function definition must be names.")]
+    fn function_definition_number_param() {
+        test_helper("f(x, 1) = x", "");
+    }
+
+    #[test]
+    #[should_panic(expected = "The parameters of a function definition must be names.")]
+    fn function_definition_named_param() {
+        test_helper("f(k: x) = x", "");
+    }
+
+    #[test]
+    #[should_panic(expected = "The function being defined must be a name.")]
+    fn function_definition_of_call() {
+        test_helper("f(x)(y) = x", "");
+    }
+
+    #[test]
+    fn lambda() {
+        assert!(test_helper("x => y => x + y", "(x => (y => (x + y)))"));
+        assert!(test_helper("f = x => x ? a : b", "(f = (x => (x ? a : b)))"));
+        assert!(test_helper("f(x => x, y)", "f((x => x), y)"));
+        assert!(test_helper("(x => x)(a)", "(x => x)(a)"));
+    }
+
+    #[test]
+    #[should_panic(expected = "The parameter of a lambda must be a name.")]
+    fn lambda_of_sum() {
+        test_helper("a + b => c", "");
+    }
+
     #[test]
     fn grouping() {
         assert!(test_helper("a + (b + c) + d", "((a + (b + c)) + d)"));
         assert!(test_helper("a ^ (b + c)", "(a ^ (b + c))"));
         assert!(test_helper("( !a ) !", "((!a)!)"));
     }
+
+    #[test]
+    fn pipe() {
+        assert!(test_helper("a |> f", "(a |> f)"));
+        assert!(test_helper("a |> f(b) |> g()", "((a |> f(b)) |> g())"));
+        assert!(test_helper("x = a + b |> f", "(x = ((a + b) |> f))"));
+        assert!(test_helper("a ? b : c |> f", "((a ? b : c) |> f)"));
+    }
+
+    #[test]
+    fn pipe_desugar() {
+        assert!(desugar_helper("data |> filter(p) |> sum()", "sum(filter(data, p))"));
+        assert!(desugar_helper("a |> f", "f(a)"));
+        assert!(desugar_helper("a + b |> f(c)(d)", "f(c)((a + b), d)"));
+        assert!(desugar_helper("x = a |> f", "(x = f(a))"));
+        assert!(desugar_helper("a |> f(b: c)", "f(a, b: c)"));
+    }
+
+    #[test]
+    fn numbers() {
+        assert!(test_helper("1 + 2.5 * x", "(1 + (2.5 * x))"));
+        assert!(test_helper("f(1, -2)", "f(1, (-2))"));
+    }
+
+    #[test]
+    fn implicit_multiplication() {
+        assert!(juxtaposition_helper(false, "2x", "(2 * x)"));
+        assert!(juxtaposition_helper(false, "a b c", "((a * b) * c)"));
+        assert!(juxtaposition_helper(false, "2x^2 + 3x", "((2 * (x ^ 2)) + (3 * x))"));
+        assert!(juxtaposition_helper(false, "-2x", "((-2) * x)"));
+        assert!(juxtaposition_helper(false, "a -b", "(a - b)"));
+        assert!(juxtaposition_helper(false, "a! b", "((a!) * b)"));
+        assert!(juxtaposition_helper(false, "a ? 2b : c", "(a ? (2 * b) : c)"));
+        assert!(juxtaposition_helper(false, "f(x) y", "(f(x) * y)"));
+    }
+
+    #[test]
+    fn application_by_adjacency() {
+        assert!(juxtaposition_helper(true, "f x", "f(x)"));
+        assert!(juxtaposition_helper(true, "f x y", "f(x)(y)"));
+        assert!(juxtaposition_helper(true, "f x + g y", "(f(x) + g(y))"));
+        assert!(juxtaposition_helper(true, "- f x", "(-f(x))"));
+        assert!(juxtaposition_helper(true, "f (a + b) c", "f((a + b))(c)"));
+        assert!(juxtaposition_helper(true, "a = f x ? g x : y", "(a = (f(x) ? g(x) : y))"));
+    }
+
+    #[test]
+    fn no_juxtaposition_by_default() {
+        assert!(test_helper("2 x", "2"));
+        assert!(test_helper("f x y", "f"));
+    }
+
+    #[test]
+    fn mixfix_prefix() {
+        assert!(mixfix_helper(&[&IF], "if a then b else c", "(if a then b else c)"));
+        assert!(mixfix_helper(&[&IF], "if a then b else c + d", "(if a then b else (c + d))"));
+        assert!(mixfix_helper(&[&IF], "x = if a then b else c", "(x = (if a then b else c))"));
+        assert!(mixfix_helper(
+            &[&IF],
+            "if a then if b then c else d else e",
+            "(if a then (if b then c else d) else e)"
+        ));
+    }
+
+    #[test]
+    fn mixfix_infix() {
+        assert!(mixfix_helper(&[&SLICE], "a[b..c]", "(a [ b .. c ])"));
+        assert!(mixfix_helper(&[&SLICE], "a[b..c][d..e]", "((a [ b .. c ]) [ d .. e ])"));
+        assert!(mixfix_helper(&[&SLICE], "f(x)[a + b .. c] + d", "((f(x) [ (a + b) .. c ]) + d)"));
+        assert!(mixfix_helper(&[&SLICE], "- a[b..c]", "(-(a [ b .. c ]))"));
+        assert!(mixfix_helper(
+            &[&IF, &SLICE],
+            "if a then b[c..d] else e",
+            "(if a then (b [ c .. d ]) else e)"
+        ));
+    }
+
+    #[test]
+    fn mixfix_matches_conditional() {
+        assert!(mixfix_helper(&[&COND], "a ? b : c ? d : e", "(a ? b : (c ? d : e))"));
+        assert!(mixfix_helper(&[&COND], "a ? b ? c : d : e", "(a ? (b ? c : d) : e)"));
+        assert!(mixfix_helper(
+            &[&COND],
+            "a + b ? c * d : e / f",
+            "((a + b) ? (c * d) : (e / f))"
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "Expected keyword \"then\"")]
+    fn mixfix_missing_keyword() {
+        mixfix_helper(&[&IF], "if a else b", "");
+    }
+
+    #[test]
+    #[should_panic(expected = "Could not parse")]
+    fn mixfix_keyword_is_reserved() {
+        mixfix_helper(&[&IF], "then + a", "");
+    }
+
+    #[test]
+    fn spans() {
+        assert_eq!(
+            spans_helper(&[], " a = f(b, k: c)! + -(d) "),
+            ["a = f(b, k: c)! + -(d)", "f(b, k: c)! + -(d)", "f(b, k: c)!", "f(b, k: c)", "f", "b", "c", "-(d)", "(d)"]
+        );
+        assert_eq!(spans_helper(&[], "x ? y : z |> g"), ["x ? y : z |> g", "x ? y : z", "x", "y", "z", "g"]);
+        assert_eq!(
+            spans_helper(&[&IF, &SLICE], "if a then b[c..d] else e"),
+            ["if a then b[c..d] else e", "a", "b[c..d]", "b", "c", "d", "e"]
+        );
+    }
 }
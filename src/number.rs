@@ -0,0 +1,357 @@
+use crate::bigint::BigInt;
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, Mul, Neg, Sub};
+
+/// The largest integer result `^` computes exactly, in bits, before giving
+/// up on it as too large.
+const MAX_POW_BITS: u64 = 1 << 20;
+
+/// The largest `n` whose factorial `n!` is computed.
+const MAX_FACTORIAL: i64 = 20_000;
+
+/// A number in the evaluator's tower.
+///
+/// Integers are exact: an `Int` result that would overflow is promoted to a
+/// `Big`, and a `Big` that fits an `i64` again is always an `Int`. Floats
+/// follow IEEE 754, and an operation with a float on either side converts
+/// the other side to the nearest float and gives a float, so `2 * 0.5` is
+/// the float `1.0`.
+///
+/// Dividing integers gives an integer if the division is exact and a float
+/// otherwise, and dividing an integer by zero is an error. `^` of integers
+/// is exact for exponents of zero and up and a float for negative ones.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Number {
+    Int(i64),
+    Big(BigInt),
+    Float(f64),
+}
+
+impl Number {
+    /// Read a number literal: digits are an integer of any size, digits with
+    /// a fraction a float.
+    pub fn parse(literal: &str) -> Option<Self> {
+        if literal.contains('.') {
+            return literal.parse().ok().map(Number::Float);
+        }
+        match literal.parse() {
+            Ok(n) => Some(Number::Int(n)),
+            Err(_) => BigInt::parse(literal).map(Number::Big),
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        match self {
+            Number::Int(n) => *n == 0,
+            Number::Big(n) => n.is_zero(),
+            Number::Float(x) => *x == 0.0,
+        }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        match self {
+            Number::Int(n) => *n as f64,
+            Number::Big(n) => n.to_f64(),
+            Number::Float(x) => *x,
+        }
+    }
+
+    // `None` for floats
+    fn to_big(&self) -> Option<BigInt> {
+        match self {
+            Number::Int(n) => Some(BigInt::from(*n)),
+            Number::Big(n) => Some(n.clone()),
+            Number::Float(_) => None,
+        }
+    }
+
+    fn from_big(n: BigInt) -> Self {
+        match n.to_i64() {
+            Some(n) => Number::Int(n),
+            None => Number::Big(n),
+        }
+    }
+
+    // the float operation if either side is a float, otherwise the `i64`
+    // one, falling back to the `BigInt` one when that overflows
+    fn combine(
+        &self,
+        other: &Number,
+        int: fn(i64, i64) -> Option<i64>,
+        big: fn(&BigInt, &BigInt) -> BigInt,
+        float: fn(f64, f64) -> f64,
+    ) -> Number {
+        match (self, other) {
+            (Number::Int(a), Number::Int(b)) => match int(*a, *b) {
+                Some(n) => Number::Int(n),
+                None => Number::from_big(big(&BigInt::from(*a), &BigInt::from(*b))),
+            },
+            (Number::Float(_), _) | (_, Number::Float(_)) => {
+                Number::Float(float(self.to_f64(), other.to_f64()))
+            }
+            _ => Number::from_big(big(&self.to_big().unwrap(), &other.to_big().unwrap())),
+        }
+    }
+
+    pub fn div(&self, other: &Number) -> Result<Number, String> {
+        let (Some(a), Some(b)) = (self.to_big(), other.to_big()) else {
+            return Ok(Number::Float(self.to_f64() / other.to_f64()));
+        };
+        let (quotient, remainder) = a.div_rem(&b).ok_or("Division by zero.")?;
+        if remainder.is_zero() {
+            Ok(Number::from_big(quotient))
+        } else {
+            Ok(Number::Float(self.to_f64() / other.to_f64()))
+        }
+    }
+
+    pub fn pow(&self, exp: &Number) -> Result<Number, String> {
+        let (Some(base), Some(exp)) = (self.to_big(), exp.to_big()) else {
+            return Ok(Number::Float(self.to_f64().powf(exp.to_f64())));
+        };
+        if exp.is_negative() {
+            return Ok(Number::Float(base.to_f64().powf(exp.to_f64())));
+        }
+        // only 0, 1 and -1 stay small for any exponent
+        let magnitude = base.bits();
+        if magnitude <= 1 {
+            let odd = exp.div_rem(&BigInt::from(2)).unwrap().1.to_i64() != Some(0);
+            let n = base.to_i64().unwrap();
+            return Ok(Number::Int(match n {
+                -1 if !odd => 1,
+                0 if exp.is_zero() => 1,
+                n => n,
+            }));
+        }
+        match exp.to_i64() {
+            Some(exp) if (exp as u64).saturating_mul(magnitude) <= MAX_POW_BITS => {
+                Ok(Number::from_big(base.pow(exp as u64)))
+            }
+            _ => Err("The result of `^` is too large.".to_string()),
+        }
+    }
+
+    pub fn abs(&self) -> Number {
+        match self {
+            Number::Float(x) => Number::Float(x.abs()),
+            n if n < &Number::Int(0) => -n,
+            n => n.clone(),
+        }
+    }
+
+    /// The integer nearest to a float by `round`, like `f64::floor`.
+    /// Integers are returned as they are.
+    pub fn to_integer(&self, round: fn(f64) -> f64) -> Result<Number, String> {
+        match self {
+            Number::Float(x) => BigInt::from_f64(round(*x))
+                .map(Number::from_big)
+                .ok_or_else(|| format!("{self} has no nearest integer.")),
+            n => Ok(n.clone()),
+        }
+    }
+
+    /// `~n`, which is `-n - 1`, for integers.
+    pub fn bitwise_not(&self) -> Result<Number, String> {
+        match self {
+            Number::Int(n) => Ok(Number::Int(!n)),
+            Number::Big(n) => Ok(Number::from_big(&-n - &BigInt::from(1))),
+            Number::Float(_) => Err(format!("`~` needs an integer, found {self}.")),
+        }
+    }
+
+    /// `n!` for integers from zero up.
+    pub fn factorial(&self) -> Result<Number, String> {
+        let n = match self {
+            Number::Int(n) if *n >= 0 => *n,
+            Number::Big(n) if !n.is_negative() => i64::MAX,
+            _ => return Err(format!("Factorial of {self} is not defined.")),
+        };
+        if n > MAX_FACTORIAL {
+            return Err(format!("Factorial of {self} is too large."));
+        }
+        let mut product = BigInt::from(1);
+        for factor in 2..=n {
+            product = product.mul_small(factor as u32);
+        }
+        Ok(Number::from_big(product))
+    }
+}
+
+/// Integers compare exactly, and with a float as floats. NaN is unordered.
+impl PartialOrd for Number {
+    fn partial_cmp(&self, other: &Number) -> Option<Ordering> {
+        match (self, other) {
+            (Number::Int(a), Number::Int(b)) => a.partial_cmp(b),
+            (Number::Float(_), _) | (_, Number::Float(_)) => {
+                self.to_f64().partial_cmp(&other.to_f64())
+            }
+            _ => self.to_big().partial_cmp(&other.to_big()),
+        }
+    }
+}
+
+impl From<i64> for Number {
+    fn from(n: i64) -> Self {
+        Number::Int(n)
+    }
+}
+
+impl From<f64> for Number {
+    fn from(x: f64) -> Self {
+        Number::Float(x)
+    }
+}
+
+/// Integers in decimal, floats always with a fraction or exponent, so `2.0`
+/// doesn't read as the integer `2`.
+impl fmt::Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Number::Int(n) => write!(f, "{n}"),
+            Number::Big(n) => write!(f, "{n}"),
+            Number::Float(x) => write!(f, "{x:?}"),
+        }
+    }
+}
+
+impl Add for &Number {
+    type Output = Number;
+
+    fn add(self, other: &Number) -> Number {
+        self.combine(other, i64::checked_add, |a, b| a + b, |a, b| a + b)
+    }
+}
+
+impl Sub for &Number {
+    type Output = Number;
+
+    fn sub(self, other: &Number) -> Number {
+        self.combine(other, i64::checked_sub, |a, b| a - b, |a, b| a - b)
+    }
+}
+
+impl Mul for &Number {
+    type Output = Number;
+
+    fn mul(self, other: &Number) -> Number {
+        self.combine(other, i64::checked_mul, |a, b| a * b, |a, b| a * b)
+    }
+}
+
+impl Neg for &Number {
+    type Output = Number;
+
+    fn neg(self) -> Number {
+        match self {
+            Number::Int(n) => match n.checked_neg() {
+                Some(n) => Number::Int(n),
+                None => Number::Big(-&BigInt::from(*n)),
+            },
+            Number::Big(n) => Number::from_big(-n),
+            Number::Float(x) => Number::Float(-x),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn int(n: i64) -> Number {
+        Number::Int(n)
+    }
+
+    #[test]
+    fn overflow_promotes_and_demotes() {
+        let max = int(i64::MAX);
+        let big = &max + &int(1);
+        assert!(matches!(big, Number::Big(_)));
+        assert_eq!(big.to_string(), "9223372036854775808");
+        assert_eq!(&big - &int(1), max);
+        assert_eq!(-&int(i64::MIN), big);
+        assert_eq!(
+            (&max * &max).to_string(),
+            "85070591730234615847396907784232501249"
+        );
+        assert_eq!(
+            Number::parse("99999999999999999999"),
+            Some(Number::Big(BigInt::parse("99999999999999999999").unwrap()))
+        );
+    }
+
+    #[test]
+    fn floats_are_contagious() {
+        assert_eq!(&int(2) * &Number::Float(0.5), Number::Float(1.0));
+        assert_eq!(Number::Float(1.0).to_string(), "1.0");
+        let big = Number::parse("100000000000000000000").unwrap();
+        assert_eq!(&big + &Number::Float(0.5), Number::Float(1e20));
+        assert!(matches!(
+            Number::Float(0.0).div(&Number::Float(0.0)),
+            Ok(Number::Float(x)) if x.is_nan()
+        ));
+        assert_eq!(
+            int(1).div(&Number::Float(0.0)),
+            Ok(Number::Float(f64::INFINITY))
+        );
+    }
+
+    #[test]
+    fn integer_division() {
+        assert_eq!(int(6).div(&int(3)), Ok(int(2)));
+        assert_eq!(int(7).div(&int(2)), Ok(Number::Float(3.5)));
+        assert_eq!(int(1).div(&int(0)), Err("Division by zero.".to_string()));
+        assert_eq!(
+            int(i64::MIN).div(&int(-1)).unwrap().to_string(),
+            "9223372036854775808"
+        );
+    }
+
+    #[test]
+    fn powers() {
+        assert_eq!(int(2).pow(&int(10)), Ok(int(1024)));
+        assert_eq!(
+            int(2).pow(&int(100)).unwrap().to_string(),
+            "1267650600228229401496703205376"
+        );
+        assert_eq!(int(2).pow(&int(-1)), Ok(Number::Float(0.5)));
+        assert_eq!(
+            Number::Float(4.0).pow(&Number::Float(0.5)),
+            Ok(Number::Float(2.0))
+        );
+        let huge = Number::parse("100000000000000000000000").unwrap();
+        assert_eq!(int(-1).pow(&huge), Ok(int(1)));
+        assert_eq!(int(0).pow(&int(0)), Ok(int(1)));
+        assert!(int(3).pow(&huge).is_err());
+        assert_eq!(int(10).pow(&int(1000)).unwrap().to_string().len(), 1001);
+    }
+
+    #[test]
+    fn ordering_and_rounding() {
+        let big = Number::parse("100000000000000000001").unwrap();
+        assert!(int(3) < big && -&big < int(3) && int(2) < Number::Float(2.5));
+        assert_eq!(Number::Float(f64::NAN).partial_cmp(&int(1)), None);
+        assert_eq!((-&big).abs(), big);
+        assert_eq!(int(i64::MIN).abs().to_string(), "9223372036854775808");
+        assert_eq!(Number::Float(-2.5).to_integer(f64::round), Ok(int(-3)));
+        assert_eq!(Number::Float(2.5).to_integer(f64::floor), Ok(int(2)));
+        assert_eq!(
+            Number::Float(1e20).to_integer(f64::ceil),
+            Ok(Number::parse("100000000000000000000").unwrap())
+        );
+        assert!(Number::Float(f64::NAN).to_integer(f64::round).is_err());
+    }
+
+    #[test]
+    fn factorials() {
+        assert_eq!(int(0).factorial(), Ok(int(1)));
+        assert_eq!(int(20).factorial(), Ok(int(2432902008176640000)));
+        assert_eq!(
+            int(30).factorial().unwrap().to_string(),
+            "265252859812191058636308480000000"
+        );
+        assert!(int(-1).factorial().is_err());
+        assert!(Number::Float(2.5).factorial().is_err());
+        assert!(int(1_000_000).factorial().is_err());
+    }
+}
@@ -0,0 +1,274 @@
+use crate::eval::{Value, ValueType};
+use crate::token::TokenType;
+use rustc_hash::FxHashMap;
+use std::fmt;
+use std::rc::Rc;
+
+pub(crate) type Unary = dyn Fn(&Value) -> Result<Value, String>;
+pub(crate) type Binary = dyn Fn(&Value, &Value) -> Result<Value, String>;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Fixity {
+    Prefix,
+    Postfix,
+}
+
+/// What the operators mean to `eval`, kept apart from the grammar so the
+/// same parse can be given different semantics.
+///
+/// An operator is looked up by its token, fixity and the type of its
+/// operand, the left one for infix operators, and then by its token and
+/// fixity alone. So `infix_for(TokenType::Slash, ValueType::Int, ..)`
+/// changes `/` on integers and leaves it alone for everything else. `++`
+/// and `--` update variables and aren't looked up here.
+///
+/// Implementations report errors as a message, which `eval` points at the
+/// operator's node.
+#[derive(Clone)]
+pub struct OperatorTable {
+    unary: FxHashMap<(TokenType, Fixity, Option<ValueType>), Rc<Unary>>,
+    infix: FxHashMap<(TokenType, Option<ValueType>), Rc<Binary>>,
+}
+
+impl OperatorTable {
+    /// A table without any operators.
+    pub fn empty() -> Self {
+        Self {
+            unary: FxHashMap::default(),
+            infix: FxHashMap::default(),
+        }
+    }
+
+    /// The operators of `BantamParser::new` on numbers: prefix `+` and `-`,
+    /// `~` as bitwise not of integers, prefix `!` as logical not, giving `1`
+    /// for zero and `0` otherwise, postfix `!` as factorial, and `+`, `-`,
+    /// `*`, `/` and `^` as in `Number`.
+    pub fn standard() -> Self {
+        let mut table = Self::empty();
+        table
+            .unary(TokenType::Plus, Fixity::Prefix, |a| {
+                Ok(a.as_number()?.clone().into())
+            })
+            .unary(TokenType::Minus, Fixity::Prefix, |a| {
+                Ok((-a.as_number()?).into())
+            })
+            .unary(TokenType::Tilde, Fixity::Prefix, |a| {
+                a.as_number()?.bitwise_not().map(Value::from)
+            })
+            .unary(TokenType::Bang, Fixity::Prefix, |a| {
+                Ok(Value::from(a.as_number()?.is_zero() as i64))
+            })
+            .unary(TokenType::Bang, Fixity::Postfix, |a| {
+                a.as_number()?.factorial().map(Value::from)
+            })
+            .infix(TokenType::Plus, |a, b| {
+                Ok((a.as_number()? + b.as_number()?).into())
+            })
+            .infix(TokenType::Minus, |a, b| {
+                Ok((a.as_number()? - b.as_number()?).into())
+            })
+            .infix(TokenType::Asterisk, |a, b| {
+                Ok((a.as_number()? * b.as_number()?).into())
+            })
+            .infix(TokenType::Slash, |a, b| {
+                a.as_number()?.div(b.as_number()?).map(Value::from)
+            })
+            .infix(TokenType::Caret, |a, b| {
+                a.as_number()?.pow(b.as_number()?).map(Value::from)
+            });
+        table
+    }
+
+    /// Define a prefix or postfix operator for operands of any type.
+    pub fn unary(
+        &mut self,
+        op: TokenType,
+        fixity: Fixity,
+        f: impl Fn(&Value) -> Result<Value, String> + 'static,
+    ) -> &mut Self {
+        self.unary.insert((op, fixity, None), Rc::new(f));
+        self
+    }
+
+    /// Define a prefix or postfix operator for operands of type `typ`,
+    /// overriding the one for any type.
+    pub fn unary_for(
+        &mut self,
+        op: TokenType,
+        fixity: Fixity,
+        typ: ValueType,
+        f: impl Fn(&Value) -> Result<Value, String> + 'static,
+    ) -> &mut Self {
+        self.unary.insert((op, fixity, Some(typ)), Rc::new(f));
+        self
+    }
+
+    pub fn infix(
+        &mut self,
+        op: TokenType,
+        f: impl Fn(&Value, &Value) -> Result<Value, String> + 'static,
+    ) -> &mut Self {
+        self.infix.insert((op, None), Rc::new(f));
+        self
+    }
+
+    /// Define an infix operator for left operands of type `typ`.
+    pub fn infix_for(
+        &mut self,
+        op: TokenType,
+        typ: ValueType,
+        f: impl Fn(&Value, &Value) -> Result<Value, String> + 'static,
+    ) -> &mut Self {
+        self.infix.insert((op, Some(typ)), Rc::new(f));
+        self
+    }
+
+    // the implementation for operands of any type, if the operator has no
+    // others, so that it doesn't depend on the operand
+    pub(crate) fn get_unary(&self, op: TokenType, fixity: Fixity) -> Option<Rc<Unary>> {
+        let typed = self.unary.keys().any(|&(other, other_fixity, typ)| {
+            (other, other_fixity) == (op, fixity) && typ.is_some()
+        });
+        if typed {
+            return None;
+        }
+        self.unary.get(&(op, fixity, None)).cloned()
+    }
+
+    pub(crate) fn get_infix(&self, op: TokenType) -> Option<Rc<Binary>> {
+        let typed = self
+            .infix
+            .keys()
+            .any(|&(other, typ)| other == op && typ.is_some());
+        if typed {
+            return None;
+        }
+        self.infix.get(&(op, None)).cloned()
+    }
+
+    // whether the operator has an implementation for any operand
+    pub(crate) fn defines_unary(&self, op: TokenType, fixity: Fixity) -> bool {
+        self.unary
+            .keys()
+            .any(|&(other, other_fixity, _)| (other, other_fixity) == (op, fixity))
+    }
+
+    pub(crate) fn defines_infix(&self, op: TokenType) -> bool {
+        self.infix.keys().any(|&(other, _)| other == op)
+    }
+
+    /// `None` if the operator isn't defined for `operand`.
+    pub fn apply_unary(
+        &self,
+        op: TokenType,
+        fixity: Fixity,
+        operand: &Value,
+    ) -> Option<Result<Value, String>> {
+        let f = self
+            .unary
+            .get(&(op, fixity, Some(operand.typ())))
+            .or_else(|| self.unary.get(&(op, fixity, None)))?;
+        Some(f(operand))
+    }
+
+    /// `None` if the operator isn't defined for `left`.
+    pub fn apply_infix(
+        &self,
+        op: TokenType,
+        left: &Value,
+        right: &Value,
+    ) -> Option<Result<Value, String>> {
+        let f = self
+            .infix
+            .get(&(op, Some(left.typ())))
+            .or_else(|| self.infix.get(&(op, None)))?;
+        Some(f(left, right))
+    }
+}
+
+impl Default for OperatorTable {
+    fn default() -> Self {
+        Self::standard()
+    }
+}
+
+impl fmt::Debug for OperatorTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OperatorTable")
+            .field("unary", &self.unary.keys().collect::<Vec<_>>())
+            .field("infix", &self.infix.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::{eval, Env, EvalError};
+    use crate::number::Number;
+    use crate::parser::BantamParser;
+    use crate::token::Span;
+
+    fn run(source: &str, env: &mut Env) -> Result<String, EvalError> {
+        eval(&BantamParser::new(source).parse_expression(), env).map(|value| value.to_string())
+    }
+
+    fn value(source: &str) -> String {
+        run(source, &mut Env::new()).unwrap()
+    }
+
+    #[test]
+    fn standard_operators() {
+        assert_eq!(value("~5"), "-6");
+        assert_eq!(value("~~(2 ^ 70)"), "1180591620717411303424");
+        assert_eq!(value("~(0 - 2 ^ 70)"), "1180591620717411303423");
+        assert_eq!(value("!0 + !7 + !0.0"), "2");
+        assert_eq!(value("-3! + 2 ^ 3"), "2");
+        assert_eq!(
+            run("1 + ~1.5", &mut Env::new()),
+            Err(EvalError {
+                span: Span::new(4, 8),
+                message: "`~` needs an integer, found 1.5.".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn overrides_per_value_type() {
+        let mut env = Env::new();
+        env.operators()
+            .infix_for(TokenType::Slash, ValueType::Int, |a, b| match (a, b) {
+                (Value::Number(Number::Int(a)), Value::Number(Number::Int(b))) if *b != 0 => {
+                    Ok(Value::from(a.div_euclid(*b)))
+                }
+                _ => Err("Integer division by zero.".to_string()),
+            });
+        assert_eq!(run("7 / 2", &mut env), Ok("3".to_string()));
+        assert_eq!(run("7.0 / 2", &mut env), Ok("3.5".to_string()));
+        assert_eq!(
+            run("0 - 7 / 0", &mut env).unwrap_err().message,
+            "Integer division by zero."
+        );
+    }
+
+    #[test]
+    fn same_grammar_other_semantics() {
+        // `!` as boolean negation on 0 and 1 and `^` as exclusive or
+        let mut table = OperatorTable::empty();
+        table
+            .unary(TokenType::Bang, Fixity::Prefix, |a| {
+                Ok(Value::from((*a == Value::from(0)) as i64))
+            })
+            .infix(TokenType::Caret, |a, b| Ok(Value::from((a != b) as i64)));
+        let mut env = Env::with_operators(table);
+        assert_eq!(run("!1 ^ 1", &mut env), Ok("1".to_string()));
+        assert_eq!(
+            run("2 + 2", &mut env).unwrap_err().message,
+            "The infix operator `+` is not defined."
+        );
+        assert_eq!(
+            run("a = 3!", &mut env).unwrap_err().message,
+            "The postfix operator `!` is not defined."
+        );
+    }
+}
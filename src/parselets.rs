@@ -1,67 +1,157 @@
-use crate::expression::Expr;
+use crate::builder::{AstBuilder, Boxed, ExprNode};
+use crate::expression::Arg;
 use crate::parser::Parser;
-use crate::token::{Token,TokenType};
+use crate::token::{Span, Token,TokenType};
+use std::borrow::Cow;
+use strum::IntoEnumIterator;
 
-pub trait InfixParselet {
+/// Parselets build their nodes through the parser's `AstBuilder` `B`, so
+/// the ones below are implemented for every builder.
+pub trait InfixParselet<B = Boxed> {
     fn parse<'source: 'callback, 'callback: 'parser, 'parser>(
         &'callback self,
-        parser: &'parser mut Parser<'callback, 'source>,
-        left: Box<Expr<'source>>,
+        parser: &'parser mut Parser<'callback, 'source, B>,
+        left: B::Node,
         token: Token,
-    ) -> Box<Expr<'source>>;
+    ) -> B::Node
+    where
+        B: AstBuilder<'source>;
 
     fn precedence(&self) -> u8;
+
+    /// Whether the right operand is parsed one precedence level lower, so
+    /// `a = b = c` groups as `a = (b = c)`.
+    fn right_assoc(&self) -> bool {
+        false
+    }
 }
 
-pub trait PrefixParselet {
+pub trait PrefixParselet<B = Boxed> {
     fn parse<'source: 'callback, 'callback: 'parser, 'parser>(
         &'callback self,
-        parser: &'parser mut Parser<'callback, 'source>,
+        parser: &'parser mut Parser<'callback, 'source, B>,
         token: Token<'source>,
-    ) -> Box<Expr<'source>>;
+    ) -> B::Node
+    where
+        B: AstBuilder<'source>;
+
+    /// The precedence the operand is parsed with, for prefix operators.
+    fn precedence(&self) -> Option<u8> {
+        None
+    }
 }
 
 pub struct Assign;
 
-impl InfixParselet for Assign {
+impl<B> InfixParselet<B> for Assign {
     fn parse<'s:'c, 'c: 'p, 'p>(
         &'c self,
-        parser: &'p mut Parser<'c, 's>,
-        left: Box<Expr<'s>>,
+        parser: &'p mut Parser<'c, 's, B>,
+        left: B::Node,
         _token: Token,
-    ) -> Box<Expr<'s>> {
-        let right = parser.parse_expression_prec(self.precedence() - 1);
-        let Expr::Name(name) = *left else {
-            panic!("The left-hand side of an assignment must be a name.");
+    ) -> B::Node
+    where
+        B: AstBuilder<'s>,
+    {
+        let right = parser.parse_expression_prec(crate::Precedence::Assignment as u8 - 1);
+        match parser.builder().take(left) {
+            ExprNode::Name { name, span } => {
+                let span = span.to(parser.span(&right));
+                parser.build(ExprNode::Assign { name, right, span })
+            }
+            // `f(x, y) = body` defines a function
+            ExprNode::Call { func, args, span } => {
+                let name = take_name(parser, func, "The function being defined must be a name.");
+                let params = args.into_iter().map(|arg| match arg {
+                    Arg::Positional(value) => take_name(parser, value, PARAMS),
+                    _ => panic!("{PARAMS}"),
+                }).collect();
+                let span = span.to(parser.span(&right));
+                parser.build(ExprNode::FnDef { name, params, body: right, span })
+            }
+            _ => panic!("The left-hand side of an assignment must be a name or a call like `f(x)`."),
+        }
+    }
+
+    fn precedence(&self) -> u8 {
+        crate::Precedence::Assignment as u8
+    }
+
+    fn right_assoc(&self) -> bool {
+        true
+    }
+}
+
+const PARAMS: &str = "The parameters of a function definition must be names.";
+
+fn take_name<'s: 'c, 'c, B>(parser: &mut Parser<'c, 's, B>, node: B::Node, message: &str) -> Cow<'s, str>
+where
+    B: AstBuilder<'s>,
+{
+    match parser.builder().take(node) {
+        ExprNode::Name { name, .. } => name,
+        _ => panic!("{message}"),
+    }
+}
+
+/// `x => body`, right associative like `=`, so `x => y => x + y` curries.
+pub struct Lambda;
+
+impl<B> InfixParselet<B> for Lambda {
+    fn parse<'s:'c, 'c: 'p, 'p>(
+        &'c self,
+        parser: &'p mut Parser<'c, 's, B>,
+        left: B::Node,
+        _token: Token,
+    ) -> B::Node
+    where
+        B: AstBuilder<'s>,
+    {
+        let body = parser.parse_expression_prec(crate::Precedence::Assignment as u8 - 1);
+        let ExprNode::Name { name: param, span } = parser.builder().take(left) else {
+            panic!("The parameter of a lambda must be a name.");
         };
 
-        Box::new(Expr::Assign { name, right })
+        let span = span.to(parser.span(&body));
+        parser.build(ExprNode::Lambda { param, body, span })
     }
 
     fn precedence(&self) -> u8 {
         crate::Precedence::Assignment as u8
     }
+
+    fn right_assoc(&self) -> bool {
+        true
+    }
 }
 
 pub struct Cond;
 
-impl InfixParselet for Cond {
+impl<B> InfixParselet<B> for Cond {
     fn parse<'s:'c, 'c: 'p, 'p>(
         &'c self,
-        parser: &'p mut Parser<'c, 's>,
-        left: Box<Expr<'s>>,
+        parser: &'p mut Parser<'c, 's, B>,
+        left: B::Node,
         _token: Token,
-    ) -> Box<Expr<'s>> {
+    ) -> B::Node
+    where
+        B: AstBuilder<'s>,
+    {
         let then_arm = parser.parse_expression();
         parser.consume_type(TokenType::Colon);
-        let else_arm = parser.parse_expression_prec(self.precedence()-1);
+        let else_arm = parser.parse_expression_prec(crate::Precedence::Conditional as u8 - 1);
 
-        Box::new(Expr::Cond { cond: left, then_arm, else_arm})
+        let span = parser.span(&left).to(parser.span(&else_arm));
+        parser.build(ExprNode::Cond { cond: left, then_arm, else_arm, span})
     }
 
     fn precedence(&self) -> u8 {
         crate::Precedence::Conditional as u8
     }
+
+    fn right_assoc(&self) -> bool {
+        true
+    }
 }
 
 pub struct Binary{
@@ -69,74 +159,117 @@ pub struct Binary{
     pub right: bool,
 }
 
-impl InfixParselet for Binary {
+impl<B> InfixParselet<B> for Binary {
     fn parse<'s:'c, 'c: 'p, 'p>(
         &'c self,
-        parser: &'p mut Parser<'c, 's>,
-        left: Box<Expr<'s>>,
+        parser: &'p mut Parser<'c, 's, B>,
+        left: B::Node,
         token: Token,
-    ) -> Box<Expr<'s>> {
+    ) -> B::Node
+    where
+        B: AstBuilder<'s>,
+    {
         let right =  parser.parse_expression_prec(self.prec - if self.right {1} else {0});
-        Box::new(Expr::Infix{ left, op: token.typ, right})
+        let span = parser.span(&left).to(parser.span(&right));
+        parser.build(ExprNode::Infix{ left, op: token.typ, right, span})
     }
 
     fn precedence(&self) -> u8 {
         self.prec
     }
+
+    fn right_assoc(&self) -> bool {
+        self.right
+    }
 }
 
 #[derive(Copy, Clone)]
 pub  struct UnaryPrefix{ pub prec: u8 }
 
-impl PrefixParselet for UnaryPrefix {
+impl<B> PrefixParselet<B> for UnaryPrefix {
     fn parse<'source: 'callback, 'callback: 'parser, 'parser>(
         &'callback self,
-        parser: &'parser mut Parser<'callback, 'source>,
+        parser: &'parser mut Parser<'callback, 'source, B>,
         token: Token,
-    ) -> Box<Expr<'source>> {
+    ) -> B::Node
+    where
+        B: AstBuilder<'source>,
+    {
         let right = parser.parse_expression_prec(self.prec);
-        Box::new(Expr::Prefix { op: token.typ, right })
+        let span = token.span.to(parser.span(&right));
+        parser.build(ExprNode::Prefix { op: token.typ, right, span })
+    }
+
+    fn precedence(&self) -> Option<u8> {
+        Some(self.prec)
     }
 }
 
 pub struct Group;
 
-impl PrefixParselet for Group {
+impl<B> PrefixParselet<B> for Group {
     fn parse<'source: 'callback, 'callback: 'parser, 'parser>(
         &'callback self,
-        parser: &'parser mut Parser<'callback, 'source>,
-        _token: Token,
-    ) -> Box<Expr<'source>> {
-        let expr = parser.parse_expression();
-        parser.consume_type(TokenType::RightParen);
+        parser: &'parser mut Parser<'callback, 'source, B>,
+        token: Token,
+    ) -> B::Node
+    where
+        B: AstBuilder<'source>,
+    {
+        let mut expr = parser.parse_expression();
+        let close = parser.consume_type(TokenType::RightParen);
+        // the group has no node of its own, so the parentheses widen the span
+        // of the expression inside
+        parser.builder().set_span(&mut expr, token.span.to(close.span));
         expr
     }
 }
 
 pub struct Name;
 
-impl PrefixParselet for Name {
+impl<B> PrefixParselet<B> for Name {
     fn parse<'source: 'callback, 'callback: 'parser, 'parser>(
         &'callback self,
-        _parser: &'parser mut Parser<'callback, 'source>,
+        parser: &'parser mut Parser<'callback, 'source, B>,
         token: Token<'source>,
-    ) -> Box<Expr<'source>> {
-        Box::new(Expr::Name(token.literal))
+    ) -> B::Node
+    where
+        B: AstBuilder<'source>,
+    {
+        parser.build(ExprNode::Name { name: token.literal, span: token.span })
     }
 }
 
 
+pub struct Number;
+
+impl<B> PrefixParselet<B> for Number {
+    fn parse<'source: 'callback, 'callback: 'parser, 'parser>(
+        &'callback self,
+        parser: &'parser mut Parser<'callback, 'source, B>,
+        token: Token<'source>,
+    ) -> B::Node
+    where
+        B: AstBuilder<'source>,
+    {
+        parser.build(ExprNode::Number { literal: token.literal, span: token.span })
+    }
+}
 
 pub struct UnaryPostfix{ pub prec: u8 }
 
-impl InfixParselet for UnaryPostfix {
+impl<B> InfixParselet<B> for UnaryPostfix {
     fn parse<'s:'c, 'c: 'p, 'p>(
         &'c self,
-        _parser: &'p mut Parser<'c, 's>,
-        left: Box<Expr<'s>>,
+        parser: &'p mut Parser<'c, 's, B>,
+        left: B::Node,
         token: Token,
-    ) -> Box<Expr<'s>> {
-        Box::new(Expr::Postfix { left, op: token.typ})
+    ) -> B::Node
+    where
+        B: AstBuilder<'s>,
+    {
+        let span = parser.span(&left).to(token.span);
+        parser.build(ExprNode::Postfix { left, op: token.typ, span})
     }
 
     fn precedence(&self) -> u8 {
@@ -146,24 +279,59 @@ impl InfixParselet for UnaryPostfix {
 
 pub struct Call;
 
-impl InfixParselet for Call {
+impl Call {
+    // `name: value`, `...value` or a plain positional expression
+    fn parse_arg<'s:'c, 'c: 'p, 'p, B: AstBuilder<'s>>(parser: &'p mut Parser<'c, 's, B>) -> Arg<'s, B::Node> {
+        if parser.match_type(TokenType::Ellipsis) {
+            return Arg::Spread(parser.parse_expression());
+        }
+        if parser.look_ahead(0).typ == TokenType::Name && parser.look_ahead(1).typ == TokenType::Colon {
+            let name = parser.consume().literal;
+            parser.consume_type(TokenType::Colon);
+            return Arg::Named { name, value: parser.parse_expression() };
+        }
+        Arg::Positional(parser.parse_expression())
+    }
+}
+
+impl<B> InfixParselet<B> for Call {
     fn parse<'s:'c, 'c: 'p, 'p>(
         &'c self,
-        parser: &'p mut Parser<'c, 's>,
-        left: Box<Expr<'s>>,
+        parser: &'p mut Parser<'c, 's, B>,
+        left: B::Node,
         _token: Token,
-    ) -> Box<Expr<'s>> {
-        let mut args: Vec<Box<Expr>> = vec![];
-        if !parser.match_type(TokenType::RightParen) {
-            loop {
-                args.push(parser.parse_expression());
-                if !parser.match_type(TokenType::Comma){
-                    break;
+    ) -> B::Node
+    where
+        B: AstBuilder<'s>,
+    {
+        let mut args: Vec<Arg<B::Node>> = vec![];
+        let close = loop {
+            if parser.look_ahead(0).typ == TokenType::RightParen {
+                break parser.consume();
+            }
+            let arg = Self::parse_arg(parser);
+            match &arg {
+                Arg::Named { name, .. } => {
+                    let duplicate = args
+                        .iter()
+                        .any(|prev| matches!(prev, Arg::Named { name: prev, .. } if prev == name));
+                    if duplicate {
+                        panic!("Duplicate named argument {:?}.", name);
+                    }
+                }
+                _ => {
+                    if args.iter().any(|prev| matches!(prev, Arg::Named { .. })) {
+                        panic!("Positional argument after named argument.");
+                    }
                 }
             }
-            parser.consume_type(TokenType::RightParen);
-        }
-        Box::new(Expr::Call {func: left, args})
+            args.push(arg);
+            if !parser.match_type(TokenType::Comma) {
+                break parser.consume_type(TokenType::RightParen);
+            }
+        };
+        let span = parser.span(&left).to(close.span);
+        parser.build(ExprNode::Call {func: left, args, span})
     }
 
     fn precedence(&self) -> u8 {
@@ -171,5 +339,171 @@ impl InfixParselet for Call {
     }
 }
 
+/// `left |> right`: with `desugar` set, the piped value becomes the first
+/// argument of the call on the right, so `a |> f(b)` parses as `f(a, b)`
+/// and a bare `a |> f` as `f(a)`.
+pub struct Pipe {
+    pub desugar: bool,
+}
 
+impl<B> InfixParselet<B> for Pipe {
+    fn parse<'s:'c, 'c: 'p, 'p>(
+        &'c self,
+        parser: &'p mut Parser<'c, 's, B>,
+        left: B::Node,
+        _token: Token,
+    ) -> B::Node
+    where
+        B: AstBuilder<'s>,
+    {
+        let right = parser.parse_expression_prec(crate::Precedence::Pipe as u8);
+        let span = parser.span(&left).to(parser.span(&right));
+        if !self.desugar {
+            return parser.build(ExprNode::Pipe { left, right, span });
+        }
+        match parser.builder().take(right) {
+            ExprNode::Call { func, mut args, .. } => {
+                args.insert(0, Arg::Positional(left));
+                parser.build(ExprNode::Call { func, args, span })
+            }
+            func => {
+                let func = parser.build(func);
+                parser.build(ExprNode::Call { func, args: vec![Arg::Positional(left)], span })
+            }
+        }
+    }
+
+    fn precedence(&self) -> u8 {
+        crate::Precedence::Pipe as u8
+    }
+}
+
+/// A multi-part operator declared by a whitespace separated pattern such as
+/// `if _ then _ else _` or `_ [ _ .. _ ]`. Each `_` is a hole, `precs` holds
+/// one precedence per hole and every other part is a punctuator or a keyword.
+///
+/// Holes are parsed with `parse_expression_prec(prec)`, except a leading
+/// hole: it is the left operand, so its precedence is the precedence of the
+/// whole operator. `Cond` is the pattern `_ ? _ : _` with
+/// `[Conditional, 0, Conditional - 1]`.
+pub struct Mixfix {
+    pub pattern: &'static str,
+    pub precs: &'static [u8],
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Part {
+    Hole,
+    Token(TokenType),
+    Keyword(&'static str),
+}
+
+impl Mixfix {
+    pub fn parts(&self) -> impl Iterator<Item = Part> {
+        let pattern = self.pattern;
+        pattern.split_whitespace().map(move |literal| {
+            if literal == "_" {
+                Part::Hole
+            } else if let Some(tt) = TokenType::iter().find(|tt| tt.punctuator() == Some(literal)) {
+                Part::Token(tt)
+            } else if literal.chars().all(char::is_alphabetic) {
+                Part::Keyword(literal)
+            } else {
+                panic!("Unknown part {:?} in mixfix pattern {:?}", literal, pattern);
+            }
+        })
+    }
+
+    // the caller has already consumed the first literal part, `span` covers
+    // everything read so far
+    fn parse_rest<'s:'c, 'c: 'p, 'p, B: AstBuilder<'s>>(
+        &'c self,
+        parser: &'p mut Parser<'c, 's, B>,
+        mut operands: Vec<B::Node>,
+        mut span: Span,
+    ) -> B::Node {
+        for part in self.parts().skip(operands.len() + 1) {
+            let end = match part {
+                Part::Hole => {
+                    let prec = self.precs[operands.len()];
+                    let operand = parser.parse_expression_prec(prec);
+                    let end = parser.span(&operand);
+                    operands.push(operand);
+                    end
+                }
+                Part::Token(tt) => parser.consume_type(tt).span,
+                Part::Keyword(keyword) => parser.consume_keyword(keyword).span,
+            };
+            span = span.to(end);
+        }
+        parser.build(ExprNode::Mixfix { pattern: Cow::Borrowed(self.pattern), operands, span })
+    }
+}
+
+impl<B> PrefixParselet<B> for Mixfix {
+    fn parse<'source: 'callback, 'callback: 'parser, 'parser>(
+        &'callback self,
+        parser: &'parser mut Parser<'callback, 'source, B>,
+        token: Token,
+    ) -> B::Node
+    where
+        B: AstBuilder<'source>,
+    {
+        self.parse_rest(parser, vec![], token.span)
+    }
+}
+
+impl<B> InfixParselet<B> for Mixfix {
+    fn parse<'s:'c, 'c: 'p, 'p>(
+        &'c self,
+        parser: &'p mut Parser<'c, 's, B>,
+        left: B::Node,
+        token: Token,
+    ) -> B::Node
+    where
+        B: AstBuilder<'s>,
+    {
+        let span = parser.span(&left).to(token.span);
+        self.parse_rest(parser, vec![left], span)
+    }
+
+    fn precedence(&self) -> u8 {
+        self.precs[0]
+    }
+}
+
+/// Two expressions side by side with no operator in between: `2x` as
+/// implicit multiplication, or `f x y` as `f(x)(y)` when `apply` is set.
+///
+/// Registered with `Parser::register_juxtaposition`, it is consulted when
+/// the next token has no infix or postfix parselet but can start an
+/// expression. That token is passed in without being consumed, since it
+/// belongs to the right operand.
+pub struct Juxtaposition {
+    pub prec: u8,
+    pub apply: bool,
+}
 
+impl<B> InfixParselet<B> for Juxtaposition {
+    fn parse<'s:'c, 'c: 'p, 'p>(
+        &'c self,
+        parser: &'p mut Parser<'c, 's, B>,
+        left: B::Node,
+        _token: Token,
+    ) -> B::Node
+    where
+        B: AstBuilder<'s>,
+    {
+        let right = parser.parse_expression_prec(self.prec);
+        let span = parser.span(&left).to(parser.span(&right));
+        if self.apply {
+            parser.build(ExprNode::Call { func: left, args: vec![Arg::Positional(right)], span })
+        } else {
+            parser.build(ExprNode::Infix { left, op: TokenType::Asterisk, right, span })
+        }
+    }
+
+    fn precedence(&self) -> u8 {
+        self.prec
+    }
+}
@@ -1,61 +1,251 @@
-use crate::expression::Expr;
+use crate::builder::{AstBuilder, Boxed, ExprNode};
 use crate::lexer::Lexer;
 pub use crate::parselets::*;
-use crate::token::{Token, TokenType};
-use rustc_hash::FxHashMap;
+use crate::token::{Span, Token, TokenType};
+use rustc_hash::{FxHashMap, FxHashSet};
 
-type PrefixMap<'callback> = FxHashMap<TokenType, &'callback dyn PrefixParselet>;
-type InfixMap<'callback> = FxHashMap<TokenType, &'callback dyn InfixParselet>;
+type PrefixMap<'callback, B> = FxHashMap<TokenType, &'callback dyn PrefixParselet<B>>;
+type InfixMap<'callback, B> = FxHashMap<TokenType, &'callback dyn InfixParselet<B>>;
+type PrefixKeywordMap<'callback, B> = FxHashMap<&'callback str, &'callback dyn PrefixParselet<B>>;
+type InfixKeywordMap<'callback, B> = FxHashMap<&'callback str, &'callback dyn InfixParselet<B>>;
 
-pub struct Parser<'callback, 'source> {
-    prefix_map: PrefixMap<'callback>,
-    infix_map: InfixMap<'callback>,
+/// The registered parselets, apart from any input. A `Parser` owns one, and
+/// consumers such as the pretty printer consult it for the precedence and
+/// associativity of each operator.
+///
+/// `B` is the `AstBuilder` the parselets build nodes with. The parselets in
+/// this crate work with any builder.
+pub struct Grammar<'callback, B = Boxed> {
+    prefix_map: PrefixMap<'callback, B>,
+    infix_map: InfixMap<'callback, B>,
+    postfix_map: InfixMap<'callback, B>,
+    juxtaposition: Option<&'callback dyn InfixParselet<B>>,
+    // names reserved by mixfix patterns, handed out as `TokenType::Keyword`
+    keywords: FxHashSet<&'callback str>,
+    prefix_keyword_map: PrefixKeywordMap<'callback, B>,
+    infix_keyword_map: InfixKeywordMap<'callback, B>,
+    mixfixes: Vec<&'callback Mixfix>,
+}
+
+impl<'callback, B> Default for Grammar<'callback, B> {
+    fn default() -> Self {
+        Self {
+            prefix_map: Default::default(),
+            infix_map: Default::default(),
+            postfix_map: Default::default(),
+            juxtaposition: None,
+            keywords: Default::default(),
+            prefix_keyword_map: Default::default(),
+            infix_keyword_map: Default::default(),
+            mixfixes: Vec::new(),
+        }
+    }
+}
+
+impl<'callback, B> Grammar<'callback, B> {
+    pub fn register_prefix(&mut self, tt: TokenType, prefix: &'callback dyn PrefixParselet<B>) {
+        self.prefix_map.insert(tt, prefix);
+    }
+
+    pub fn register_infix(&mut self, tt: TokenType, infix: &'callback dyn InfixParselet<B>) {
+        self.infix_map.insert(tt, infix);
+    }
+
+    /// Unregister the infix parselet of `tt`, returning it.
+    pub fn remove_infix(&mut self, tt: TokenType) -> Option<&'callback dyn InfixParselet<B>> {
+        self.infix_map.remove(&tt)
+    }
+
+    /// Postfix operators are infix parselets that don't parse a right operand.
+    /// They live apart from the infix ones so a token can be both, see
+    /// `Parser::lookahead_infix` for how the two are told apart.
+    pub fn register_postfix(&mut self, tt: TokenType, postfix: &'callback dyn InfixParselet<B>) {
+        self.postfix_map.insert(tt, postfix);
+    }
+
+    /// Use `juxtaposition` when an operand is directly followed by another
+    /// one, as in `2x` or `f x`.
+    pub fn register_juxtaposition(&mut self, juxtaposition: &'callback dyn InfixParselet<B>) {
+        self.juxtaposition = Some(juxtaposition);
+    }
+
+    /// Reserve every keyword in the pattern and dispatch on its first
+    /// literal part: a prefix parselet when the pattern opens with a
+    /// literal, an infix one when it opens with a hole.
+    pub fn register_mixfix(&mut self, mixfix: &'callback Mixfix) {
+        for part in mixfix.parts() {
+            if let Part::Keyword(keyword) = part {
+                self.keywords.insert(keyword);
+            }
+        }
+        let mut parts = mixfix.parts();
+        match parts.next() {
+            Some(Part::Hole) => match parts.next() {
+                Some(Part::Token(tt)) => self.register_infix(tt, mixfix),
+                Some(Part::Keyword(keyword)) => {
+                    self.infix_keyword_map.insert(keyword, mixfix);
+                }
+                _ => panic!("Mixfix pattern {:?} must not start with two holes.", mixfix.pattern),
+            },
+            Some(Part::Token(tt)) => self.register_prefix(tt, mixfix),
+            Some(Part::Keyword(keyword)) => {
+                self.prefix_keyword_map.insert(keyword, mixfix);
+            }
+            None => panic!("Mixfix pattern must not be empty."),
+        }
+        self.mixfixes.push(mixfix);
+    }
+
+    pub fn prefix(&self, tok: &Token) -> Option<&'callback dyn PrefixParselet<B>> {
+        if tok.typ == TokenType::Keyword {
+            self.prefix_keyword_map.get(&*tok.literal).copied()
+        } else {
+            self.prefix_map.get(&tok.typ).copied()
+        }
+    }
+
+    pub fn infix(&self, tok: &Token) -> Option<&'callback dyn InfixParselet<B>> {
+        if tok.typ == TokenType::Keyword {
+            self.infix_keyword_map.get(&*tok.literal).copied()
+        } else {
+            self.infix_map.get(&tok.typ).copied()
+        }
+    }
+
+    pub fn postfix(&self, tok: &Token) -> Option<&'callback dyn InfixParselet<B>> {
+        self.postfix_map.get(&tok.typ).copied()
+    }
+
+    pub fn mixfix(&self, pattern: &str) -> Option<&'callback Mixfix> {
+        self.mixfixes.iter().copied().find(|mixfix| mixfix.pattern == pattern)
+    }
+
+    pub fn is_keyword(&self, name: &str) -> bool {
+        self.keywords.contains(name)
+    }
+
+    pub fn can_start_expression(&self, tok: &Token) -> bool {
+        self.prefix(tok).is_some()
+    }
+}
+
+pub struct Parser<'callback, 'source, B = Boxed> {
+    grammar: Grammar<'callback, B>,
     tokbuf: Vec<Token<'source>>,
     lexer: Lexer<'source>,
+    builder: B,
 }
 
 impl<'source: 'callback, 'callback> Parser<'callback, 'source> {
     pub fn new(lexer: Lexer<'source>) -> Self {
+        Self::with_grammar(lexer, Grammar::default())
+    }
+
+    pub fn with_grammar(lexer: Lexer<'source>, grammar: Grammar<'callback>) -> Self {
+        Self::with_builder(lexer, grammar, Boxed)
+    }
+}
+
+impl<'source: 'callback, 'callback, B: AstBuilder<'source>> Parser<'callback, 'source, B> {
+    pub fn with_builder(lexer: Lexer<'source>, grammar: Grammar<'callback, B>, builder: B) -> Self {
         Self {
-            prefix_map: PrefixMap::default(),
-            infix_map: InfixMap::default(),
+            grammar,
             tokbuf: Vec::new(),
             lexer,
+            builder,
         }
     }
 
-    pub fn register_prefix(&mut self, tt: TokenType, prefix: &'callback dyn PrefixParselet) {
-        self.prefix_map.insert(tt, prefix);
+    pub fn grammar(&self) -> &Grammar<'callback, B> {
+        &self.grammar
     }
 
-    pub fn register_infix(&mut self, tt: TokenType, infix: &'callback dyn InfixParselet) {
-        self.infix_map.insert(tt, infix);
+    pub fn builder(&mut self) -> &mut B {
+        &mut self.builder
     }
 
-    pub fn parse_expression(&mut self) -> Box<Expr<'source>> {
+    pub fn into_builder(self) -> B {
+        self.builder
+    }
+
+    pub fn build(&mut self, node: ExprNode<'source, B::Node>) -> B::Node {
+        self.builder.build(node)
+    }
+
+    pub fn span(&self, node: &B::Node) -> Span {
+        self.builder.span(node)
+    }
+
+    pub fn register_prefix(&mut self, tt: TokenType, prefix: &'callback dyn PrefixParselet<B>) {
+        self.grammar.register_prefix(tt, prefix);
+    }
+
+    pub fn register_infix(&mut self, tt: TokenType, infix: &'callback dyn InfixParselet<B>) {
+        self.grammar.register_infix(tt, infix);
+    }
+
+    pub fn register_postfix(&mut self, tt: TokenType, postfix: &'callback dyn InfixParselet<B>) {
+        self.grammar.register_postfix(tt, postfix);
+    }
+
+    pub fn register_juxtaposition(&mut self, juxtaposition: &'callback dyn InfixParselet<B>) {
+        self.grammar.register_juxtaposition(juxtaposition);
+    }
+
+    pub fn register_mixfix(&mut self, mixfix: &'callback Mixfix) {
+        self.grammar.register_mixfix(mixfix);
+    }
+
+    pub fn parse_expression(&mut self) -> B::Node {
         self.parse_expression_prec(0)
     }
 
-    pub fn parse_expression_prec(&mut self, prec: u8) -> Box<Expr<'source>> {
+    pub fn parse_expression_prec(&mut self, prec: u8) -> B::Node {
         let mut tok = self.consume();
-        let Some(prefix) = self.prefix_map.get(&tok.typ) else {
+        let Some(prefix) = self.grammar.prefix(&tok) else {
             panic!("Could not parse {:?} ", tok);
         };
         let mut left = prefix.parse(self, tok);
-        while prec < self.lookahead_precedence() {
-            tok = self.consume();
-            let infix = self.infix_map[&tok.typ];
+        while let Some((infix, adjacent)) = self.lookahead_infix() {
+            if prec >= infix.precedence() {
+                break;
+            }
+            tok = if adjacent { self.look_ahead(0) } else { self.consume() };
             left = infix.parse(self, left, tok);
         }
         left
     }
 
-    fn lookahead_precedence(&mut self) -> u8 {
-        let tt = self.look_ahead(0).typ;
-        if let Some(infix_parselet) = self.infix_map.get(&tt) {
-            infix_parselet.precedence()
-        } else {
-            0
+    /// The parselet that continues the expression with the next token.
+    ///
+    /// A token registered both as infix and as postfix operator, like `?`
+    /// for `Cond` and the try operator, is read as infix when the token after
+    /// it can start an expression and as postfix otherwise. So `a ? b : c` is
+    /// a conditional and `a? * b` multiplies `a?`, but `a? + b` is the start
+    /// of a conditional because `+` is also a prefix operator.
+    ///
+    /// Only a token with neither falls back to the juxtaposition parselet,
+    /// and only if it can start an expression, so `a -b` stays a subtraction
+    /// and `f (x)` a call. The flag is set for juxtaposition, whose token
+    /// must not be consumed.
+    fn lookahead_infix(&mut self) -> Option<(&'callback dyn InfixParselet<B>, bool)> {
+        let tok = self.look_ahead(0);
+        match (self.grammar.infix(&tok), self.grammar.postfix(&tok)) {
+            (Some(infix), Some(postfix)) => {
+                let next = self.look_ahead(1);
+                if self.grammar.can_start_expression(&next) {
+                    Some((infix, false))
+                } else {
+                    Some((postfix, false))
+                }
+            }
+            (None, None) => match self.grammar.juxtaposition {
+                Some(juxtaposition) if self.grammar.can_start_expression(&tok) => {
+                    Some((juxtaposition, true))
+                }
+                _ => None,
+            },
+            (infix, postfix) => infix.or(postfix).map(|parselet| (parselet, false)),
         }
     }
 
@@ -79,57 +269,99 @@ impl<'source: 'callback, 'callback> Parser<'callback, 'source> {
         self.consume()
     }
 
+    pub fn consume_keyword(&mut self, expected: &str) -> Token<'source> {
+        let tok = self.look_ahead(0);
+        if tok.typ != TokenType::Keyword || tok.literal != expected {
+            panic!(
+                "{}",
+                format!("Expected keyword {:?}, but found {:?}", expected, tok.literal)
+            );
+        }
+        self.consume()
+    }
+
     pub fn consume(&mut self) -> Token<'source> {
         self.look_ahead(0);
         self.tokbuf.remove(0)
     }
 
-    fn look_ahead(&mut self, distance: usize) -> Token<'source> {
+    pub fn look_ahead(&mut self, distance: usize) -> Token<'source> {
         while self.tokbuf.len() <= distance {
-            let tok = self.lexer.next_token();
+            let mut tok = self.lexer.next_token();
+            if tok.typ == TokenType::Name && self.grammar.is_keyword(&tok.literal) {
+                tok.typ = TokenType::Keyword;
+            }
             self.tokbuf.push(tok);
         }
-        self.tokbuf[distance]
+        self.tokbuf[distance].clone()
     }
 }
 
 //pub struct TokenError(String);
 //pub struct ParseError(String);
 
-pub struct BantamParser(Parser<'static, 'static>);
+pub struct BantamParser<'source, B = Boxed>(Parser<'source, 'source, B>);
 
-impl BantamParser {
-    pub fn new(source: &'static str) -> Self {
+impl<'source> BantamParser<'source> {
+    pub fn new(source: &'source str) -> Self {
+        Self::with_builder(source, Boxed)
+    }
+}
+
+impl<'source, B: AstBuilder<'source>> BantamParser<'source, B> {
+    /// Parse into the nodes of `builder`, such as an `ExprArena`.
+    pub fn with_builder(source: &'source str, builder: B) -> Self {
         let l = Lexer::new(source);
-        let mut p = Self(Parser::new(l));
+        Self(Parser::with_builder(l, Self::grammar(), builder))
+    }
+
+    /// The Bantam operator table on its own, for consumers that need the
+    /// precedences without parsing anything.
+    pub fn grammar() -> Grammar<'source, B> {
+        let mut g = Grammar::default();
 
         static NAME: Name = Name;
-        p.0.register_prefix(TokenType::Name, &NAME);
+        g.register_prefix(TokenType::Name, &NAME);
+
+        static NUMBER: Number = Number;
+        g.register_prefix(TokenType::Number, &NUMBER);
 
         static ASSIGN: Assign = Assign;
-        p.0.register_infix(TokenType::Assign, &ASSIGN);
+        g.register_infix(TokenType::Assign, &ASSIGN);
+
+        static LAMBDA: Lambda = Lambda;
+        g.register_infix(TokenType::FatArrow, &LAMBDA);
 
         static QUESTION: Cond = Cond;
-        p.0.register_infix(TokenType::Question, &QUESTION);
+        g.register_infix(TokenType::Question, &QUESTION);
 
         static GROUP: Group = Group;
-        p.0.register_prefix(TokenType::LeftParen, &GROUP);
+        g.register_prefix(TokenType::LeftParen, &GROUP);
 
         static CALL: Call = Call;
-        p.0.register_infix(TokenType::LeftParen, &CALL);
+        g.register_infix(TokenType::LeftParen, &CALL);
+
+        static PIPE: Pipe = Pipe { desugar: false };
+        g.register_infix(TokenType::Pipe, &PIPE);
 
         static PRE: UnaryPrefix = UnaryPrefix {
             prec: crate::Precedence::Prefix as u8,
         };
-        p.0.register_prefix(TokenType::Plus, &PRE);
-        p.0.register_prefix(TokenType::Minus, &PRE);
-        p.0.register_prefix(TokenType::Tilde, &PRE);
-        p.0.register_prefix(TokenType::Bang, &PRE);
+        g.register_prefix(TokenType::Plus, &PRE);
+        g.register_prefix(TokenType::Minus, &PRE);
+        g.register_prefix(TokenType::Tilde, &PRE);
+        g.register_prefix(TokenType::Bang, &PRE);
+        g.register_prefix(TokenType::PlusPlus, &PRE);
+        g.register_prefix(TokenType::MinusMinus, &PRE);
 
         static POST: UnaryPostfix = UnaryPostfix {
             prec: crate::Precedence::Postfix as u8,
         };
-        p.0.register_infix(TokenType::Bang, &POST);
+        g.register_postfix(TokenType::Bang, &POST);
+        g.register_postfix(TokenType::PlusPlus, &POST);
+        g.register_postfix(TokenType::MinusMinus, &POST);
+        g.register_postfix(TokenType::Question, &POST);
+        g.register_postfix(TokenType::Apostrophe, &POST);
 
         static SUM: Binary = Binary {
             prec: crate::Precedence::Sum as u8,
@@ -139,21 +371,57 @@ impl BantamParser {
             prec: crate::Precedence::Product as u8,
             right: false,
         };
-        p.0.register_infix(TokenType::Plus, &SUM);
-        p.0.register_infix(TokenType::Minus, &SUM);
-        p.0.register_infix(TokenType::Asterisk, &PRODUCT);
-        p.0.register_infix(TokenType::Slash, &PRODUCT);
+        g.register_infix(TokenType::Plus, &SUM);
+        g.register_infix(TokenType::Minus, &SUM);
+        g.register_infix(TokenType::Asterisk, &PRODUCT);
+        g.register_infix(TokenType::Slash, &PRODUCT);
 
         static EXP: Binary = Binary {
             prec: crate::Precedence::Exponent as u8,
             right: true,
         };
-        p.0.register_infix(TokenType::Caret, &EXP);
+        g.register_infix(TokenType::Caret, &EXP);
+
+        g
+    }
+
+    pub fn parser(&self) -> &Parser<'source, 'source, B> {
+        &self.0
+    }
 
-        p
+    pub fn into_builder(self) -> B {
+        self.0.into_builder()
+    }
+
+    pub fn register_mixfix(&mut self, mixfix: &'source Mixfix) {
+        self.0.register_mixfix(mixfix);
+    }
+
+    /// Parse `a |> f(b)` straight into `f(a, b)` instead of `Expr::Pipe`.
+    pub fn desugar_pipes(&mut self) {
+        static PIPE: Pipe = Pipe { desugar: true };
+        self.0.register_infix(TokenType::Pipe, &PIPE);
+    }
+
+    /// Read adjacent operands as a product: `2x` is `2 * x`.
+    pub fn implicit_multiplication(&mut self) {
+        static JUXTAPOSITION: Juxtaposition = Juxtaposition {
+            prec: crate::Precedence::Product as u8,
+            apply: false,
+        };
+        self.0.register_juxtaposition(&JUXTAPOSITION);
+    }
+
+    /// Read adjacent operands as function application: `f x y` is `f(x)(y)`.
+    pub fn application_by_adjacency(&mut self) {
+        static JUXTAPOSITION: Juxtaposition = Juxtaposition {
+            prec: crate::Precedence::Call as u8,
+            apply: true,
+        };
+        self.0.register_juxtaposition(&JUXTAPOSITION);
     }
 
-    pub fn parse_expression(&mut self) -> Box<Expr> {
+    pub fn parse_expression(&mut self) -> B::Node {
         self.0.parse_expression()
     }
 }
@@ -0,0 +1,500 @@
+use crate::expression::{Arg, Expr};
+use crate::parser::{Grammar, Part};
+use crate::token::{Span, Token, TokenType};
+use std::borrow::Cow;
+use strum::IntoEnumIterator;
+
+// binding of something that never gives up its operands, like a name
+const ATOM: u8 = u8::MAX;
+
+/// Where an operand sits in its parent.
+#[derive(Clone)]
+enum Slot {
+    /// Left operand of an operator with this precedence.
+    Leading(u8),
+    /// Rightmost operand, parsed with this precedence.
+    Trailing(u8),
+    /// Operand parsed with this precedence and closed by the given token,
+    /// like a call argument or the middle of a conditional.
+    Between(u8, Token<'static>),
+}
+
+/// Prints an `Expr` with only the parentheses needed to parse it back into
+/// the same tree, using the precedence and associativity registered in a
+/// `Grammar`.
+///
+/// Two properties of a node decide whether it needs parentheses. Its lead
+/// is the precedence of the operator that takes its leftmost operand, none
+/// if it starts with a token of its own. Its trail is the lowest precedence
+/// its rightmost operands are parsed with, `ATOM` if it ends with a token.
+/// A node placed before an operator must not swallow it, so its trail must
+/// reach that operator's precedence; a node placed after one must bind
+/// tighter than the precedence it is parsed with.
+pub struct Pretty<'g, 'callback> {
+    grammar: &'g Grammar<'callback>,
+}
+
+impl<'g, 'callback> Pretty<'g, 'callback> {
+    pub fn new(grammar: &'g Grammar<'callback>) -> Self {
+        Self { grammar }
+    }
+
+    pub fn print(&self, expr: &Expr, out: &mut String) {
+        self.operand(expr, Slot::Trailing(0), false, out);
+    }
+
+    // `followed` is set when the next printed token can start an expression,
+    // which turns an operator that is both infix and postfix into infix
+    fn operand(&self, expr: &Expr, slot: Slot, followed: bool, out: &mut String) {
+        if self.needs_parens(expr, slot) || (followed && self.ambiguous_postfix(expr)) {
+            out.push('(');
+            self.node(expr, false, out);
+            out.push(')');
+        } else {
+            self.node(expr, followed, out);
+        }
+    }
+
+    fn needs_parens(&self, expr: &Expr, slot: Slot) -> bool {
+        match slot {
+            Slot::Leading(prec) => self.trail(expr) < prec,
+            Slot::Trailing(prec) => self.lead(expr).is_some_and(|lead| lead <= prec),
+            Slot::Between(prec, next) => {
+                self.lead(expr).is_some_and(|lead| lead <= prec)
+                    || self.continues_with(&next).is_some_and(|next| self.trail(expr) < next)
+            }
+        }
+    }
+
+    fn ambiguous_postfix(&self, expr: &Expr) -> bool {
+        match expr {
+            Expr::Postfix { op, .. } => self.grammar.infix(&token(*op)).is_some(),
+            _ => false,
+        }
+    }
+
+    fn node(&self, expr: &Expr, followed: bool, out: &mut String) {
+        match expr {
+            Expr::Name { name, .. } => out.push_str(name),
+            Expr::Number { literal, .. } => out.push_str(literal),
+            Expr::Assign { name, right, .. } => {
+                out.push_str(name);
+                out.push_str(" = ");
+                let prec = self.infix(TokenType::Assign);
+                self.operand(right, Slot::Trailing(prec - 1), followed, out);
+            }
+            Expr::FnDef {
+                name, params, body, ..
+            } => {
+                out.push_str(name);
+                out.push('(');
+                out.push_str(&params.join(", "));
+                out.push_str(") = ");
+                let prec = self.infix(TokenType::Assign);
+                self.operand(body, Slot::Trailing(prec - 1), followed, out);
+            }
+            Expr::Lambda { param, body, .. } => {
+                out.push_str(param);
+                out.push_str(" => ");
+                let (prec, right_assoc) = self.infix_assoc(TokenType::FatArrow);
+                let slot = Slot::Trailing(prec - right_assoc as u8);
+                self.operand(body, slot, followed, out);
+            }
+            Expr::Call { func, args, .. } => {
+                let prec = self.infix(TokenType::LeftParen);
+                let paren = self.starts(TokenType::LeftParen);
+                self.operand(func, Slot::Leading(prec), paren, out);
+                out.push('(');
+                for (i, arg) in args.iter().enumerate() {
+                    let next = if i + 1 < args.len() {
+                        TokenType::Comma
+                    } else {
+                        TokenType::RightParen
+                    };
+                    let slot = Slot::Between(0, token(next));
+                    match arg {
+                        Arg::Positional(value) => self.operand(value, slot, false, out),
+                        Arg::Named { name, value } => {
+                            out.push_str(name);
+                            out.push_str(": ");
+                            self.operand(value, slot, false, out);
+                        }
+                        Arg::Spread(value) => {
+                            out.push_str("...");
+                            self.operand(value, slot, false, out);
+                        }
+                    }
+                    if i + 1 < args.len() {
+                        out.push_str(", ");
+                    }
+                }
+                out.push(')');
+            }
+            Expr::Cond {
+                cond,
+                then_arm,
+                else_arm,
+                ..
+            } => {
+                let prec = self.infix(TokenType::Question);
+                let question = self.starts(TokenType::Question);
+                self.operand(cond, Slot::Leading(prec), question, out);
+                out.push_str(" ? ");
+                let colon = self.starts(TokenType::Colon);
+                self.operand(then_arm, Slot::Between(0, token(TokenType::Colon)), colon, out);
+                out.push_str(" : ");
+                self.operand(else_arm, Slot::Trailing(prec - 1), followed, out);
+            }
+            Expr::Prefix { op, right, .. } => {
+                out.push_str(op.punctuator().unwrap());
+                let mut operand = String::new();
+                self.operand(right, Slot::Trailing(self.prefix(*op)), followed, &mut operand);
+                join(out, &operand);
+            }
+            Expr::Postfix { left, op, .. } => {
+                self.operand(left, Slot::Leading(self.postfix(*op)), self.starts(*op), out);
+                join(out, op.punctuator().unwrap());
+            }
+            Expr::Infix { left, op, right, .. } => {
+                let (prec, right_assoc) = self.infix_assoc(*op);
+                self.operand(left, Slot::Leading(prec), self.starts(*op), out);
+                out.push(' ');
+                out.push_str(op.punctuator().unwrap());
+                out.push(' ');
+                let slot = Slot::Trailing(prec - right_assoc as u8);
+                self.operand(right, slot, followed, out);
+            }
+            Expr::Pipe { left, right, .. } => {
+                let prec = self.infix(TokenType::Pipe);
+                self.operand(left, Slot::Leading(prec), self.starts(TokenType::Pipe), out);
+                out.push_str(" |> ");
+                self.operand(right, Slot::Trailing(prec), followed, out);
+            }
+            Expr::Mixfix { pattern, operands, .. } => {
+                let parts = self.mixfix_parts(pattern);
+                let mut operands = operands.iter();
+                for (i, (literal, slot)) in parts.iter().enumerate() {
+                    if i > 0 {
+                        out.push(' ');
+                    }
+                    match slot {
+                        Some(slot) => {
+                            let followed = match parts.get(i + 1) {
+                                Some((literal, _)) => self.grammar.can_start_expression(literal),
+                                None => followed,
+                            };
+                            self.operand(operands.next().unwrap(), slot.clone(), followed, out);
+                        }
+                        None => out.push_str(&literal.literal),
+                    }
+                }
+            }
+        }
+    }
+
+    fn lead(&self, expr: &Expr) -> Option<u8> {
+        match expr {
+            Expr::Name { .. } | Expr::Number { .. } | Expr::Prefix { .. } => None,
+            Expr::Assign { .. } | Expr::FnDef { .. } => Some(self.infix(TokenType::Assign)),
+            Expr::Lambda { .. } => Some(self.infix(TokenType::FatArrow)),
+            Expr::Call { .. } => Some(self.infix(TokenType::LeftParen)),
+            Expr::Cond { .. } => Some(self.infix(TokenType::Question)),
+            Expr::Postfix { op, .. } => Some(self.postfix(*op)),
+            Expr::Infix { op, .. } => Some(self.infix(*op)),
+            Expr::Pipe { .. } => Some(self.infix(TokenType::Pipe)),
+            Expr::Mixfix { pattern, .. } => match self.mixfix_parts(pattern).first() {
+                Some((_, Some(Slot::Leading(prec)))) => Some(*prec),
+                _ => None,
+            },
+        }
+    }
+
+    fn trail(&self, expr: &Expr) -> u8 {
+        let (last, slot) = match expr {
+            Expr::Name { .. } | Expr::Number { .. } | Expr::Call { .. } | Expr::Postfix { .. } => {
+                return ATOM
+            }
+            Expr::Assign { right, .. } | Expr::FnDef { body: right, .. } => {
+                (right.as_ref(), self.infix(TokenType::Assign) - 1)
+            }
+            Expr::Lambda { body, .. } => {
+                let (prec, right_assoc) = self.infix_assoc(TokenType::FatArrow);
+                (body.as_ref(), prec - right_assoc as u8)
+            }
+            Expr::Cond { else_arm, .. } => (else_arm.as_ref(), self.infix(TokenType::Question) - 1),
+            Expr::Prefix { op, right, .. } => (right.as_ref(), self.prefix(*op)),
+            Expr::Infix { op, right, .. } => {
+                let (prec, right_assoc) = self.infix_assoc(*op);
+                (right.as_ref(), prec - right_assoc as u8)
+            }
+            Expr::Pipe { right, .. } => (right.as_ref(), self.infix(TokenType::Pipe)),
+            Expr::Mixfix { pattern, operands, .. } => match self.mixfix_parts(pattern).last() {
+                Some((_, Some(Slot::Trailing(prec)))) => (operands.last().unwrap(), *prec),
+                _ => return ATOM,
+            },
+        };
+        if self.needs_parens(last, Slot::Trailing(slot)) {
+            slot
+        } else {
+            slot.min(self.trail(last))
+        }
+    }
+
+    // the literal of every part, paired with the slot of the holes
+    fn mixfix_parts(&self, pattern: &str) -> Vec<(Token<'static>, Option<Slot>)> {
+        let Some(mixfix) = self.grammar.mixfix(pattern) else {
+            // not registered: keep every compound operand in parentheses
+            return pattern
+                .split_whitespace()
+                .map(|literal| match literal {
+                    "_" => (token(TokenType::Eof), Some(Slot::Leading(ATOM))),
+                    _ => (token(TokenType::Keyword), None),
+                })
+                .collect();
+        };
+        let parts: Vec<Part> = mixfix.parts().collect();
+        let mut holes = mixfix.precs.iter();
+        parts
+            .iter()
+            .enumerate()
+            .map(|(i, part)| match part {
+                Part::Hole => {
+                    let prec = *holes.next().unwrap();
+                    let slot = match parts.get(i + 1) {
+                        _ if i == 0 => Slot::Leading(prec),
+                        None => Slot::Trailing(prec),
+                        Some(Part::Token(tt)) => Slot::Between(prec, token(*tt)),
+                        Some(Part::Keyword(keyword)) => Slot::Between(prec, keyword_token(keyword)),
+                        Some(Part::Hole) => Slot::Trailing(prec),
+                    };
+                    (token(TokenType::Eof), Some(slot))
+                }
+                Part::Token(tt) => (token(*tt), None),
+                Part::Keyword(keyword) => (keyword_token(keyword), None),
+            })
+            .collect()
+    }
+
+    // precedence of the infix or postfix operator a token would continue with
+    fn continues_with(&self, tok: &Token) -> Option<u8> {
+        self.grammar
+            .infix(tok)
+            .or_else(|| self.grammar.postfix(tok))
+            .map(|parselet| parselet.precedence())
+    }
+
+    fn starts(&self, tt: TokenType) -> bool {
+        self.grammar.can_start_expression(&token(tt))
+    }
+
+    fn infix(&self, tt: TokenType) -> u8 {
+        self.infix_assoc(tt).0
+    }
+
+    // an operator missing from the grammar binds loosest of all, so it is
+    // always parenthesized
+    fn infix_assoc(&self, tt: TokenType) -> (u8, bool) {
+        self.grammar
+            .infix(&token(tt))
+            .map_or((1, false), |parselet| (parselet.precedence(), parselet.right_assoc()))
+    }
+
+    fn prefix(&self, tt: TokenType) -> u8 {
+        self.grammar
+            .prefix(&token(tt))
+            .and_then(|parselet| parselet.precedence())
+            .unwrap_or(ATOM)
+    }
+
+    fn postfix(&self, tt: TokenType) -> u8 {
+        self.grammar
+            .postfix(&token(tt))
+            .map_or(ATOM, |parselet| parselet.precedence())
+    }
+}
+
+fn token(typ: TokenType) -> Token<'static> {
+    Token {
+        literal: Cow::Borrowed(typ.punctuator().unwrap_or("")),
+        typ,
+        span: Span::default(),
+    }
+}
+
+fn keyword_token(keyword: &'static str) -> Token<'static> {
+    Token {
+        literal: Cow::Borrowed(keyword),
+        typ: TokenType::Keyword,
+        span: Span::default(),
+    }
+}
+
+// append `text`, separated by a space if the two sides would otherwise lex
+// as one longer punctuator, as in `- -a`
+fn join(out: &mut String, text: &str) {
+    if let (Some(last), Some(first)) = (out.chars().last(), text.chars().next()) {
+        let merges = TokenType::iter().filter_map(|tt| tt.punctuator()).any(|punc| {
+            let mut chars = punc.chars();
+            chars.next() == Some(last) && chars.next() == Some(first)
+        });
+        if merges {
+            out.push(' ');
+        }
+    }
+    out.push_str(text);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expression::Print;
+    use crate::fixtures::{IF, SLICE};
+    use crate::parser::BantamParser;
+
+    fn parser(source: &str) -> BantamParser<'_> {
+        let mut parser = BantamParser::new(source);
+        parser.register_mixfix(&IF);
+        parser.register_mixfix(&SLICE);
+        parser
+    }
+
+    fn pretty(source: &str) -> String {
+        let mut parser = parser(source);
+        let expr = parser.parse_expression();
+        let mut out = String::new();
+        Pretty::new(parser.parser().grammar()).print(&expr, &mut out);
+        out
+    }
+
+    fn full(source: &str) -> String {
+        let mut out = String::new();
+        parser(source).parse_expression().print(&mut out);
+        out
+    }
+
+    fn assert_round_trip(source: &str) {
+        let printed = pretty(source);
+        assert_eq!(full(&printed), full(source), "{source} printed as {printed}");
+    }
+
+    #[test]
+    fn minimal_parentheses() {
+        assert_eq!(pretty("a = b + c * d"), "a = b + c * d");
+        assert_eq!(pretty("(a = (b + (c * d)))"), "a = b + c * d");
+        assert_eq!(pretty("(a + b) * c"), "(a + b) * c");
+        assert_eq!(pretty("(a - b) - c"), "a - b - c");
+        assert_eq!(pretty("a - (b - c)"), "a - (b - c)");
+        assert_eq!(pretty("a ^ (b ^ c)"), "a ^ b ^ c");
+        assert_eq!(pretty("(a ^ b) ^ c"), "(a ^ b) ^ c");
+        assert_eq!(pretty("a = (b = c)"), "a = b = c");
+        assert_eq!(pretty("(-a)!"), "(-a)!");
+        assert_eq!(pretty("-(a!)"), "-a!");
+        assert_eq!(pretty("-(-a)"), "- -a");
+        assert_eq!(pretty("+(++a)"), "+ ++a");
+        assert_eq!(pretty("(a?) * b"), "a? * b");
+        assert_eq!(pretty("(a?) + b"), "(a?) + b");
+        assert_eq!(pretty("f((a ? b : c), (d + e), k: (g))"), "f(a ? b : c, d + e, k: g)");
+        assert_eq!(pretty("((a ? b : c) ? d : e)"), "(a ? b : c) ? d : e");
+        assert_eq!(pretty("a ? b : (c ? d : e)"), "a ? b : c ? d : e");
+        assert_eq!(pretty("(a + b)(c)"), "(a + b)(c)");
+        assert_eq!(pretty("((a |> f) |> g)"), "a |> f |> g");
+        assert_eq!(pretty("f(x, y) = (x + y)"), "f(x, y) = x + y");
+        assert_eq!(pretty("(f() = a) * b"), "(f() = a) * b");
+        assert_eq!(pretty("x => (y => (x + y))"), "x => y => x + y");
+        assert_eq!(pretty("f = (x => x)"), "f = x => x");
+        assert_eq!(pretty("(x => x)(a) + (y => y)"), "(x => x)(a) + (y => y)");
+    }
+
+    #[test]
+    fn minimal_parentheses_mixfix() {
+        assert_eq!(pretty("a + (if b then c else d)"), "a + if b then c else d");
+        assert_eq!(pretty("(if b then c else d) + a"), "(if b then c else d) + a");
+        assert_eq!(pretty("(a + (if b then c else d)) * e"), "(a + if b then c else d) * e");
+        assert_eq!(pretty("(a + b)[(c) .. d]"), "(a + b) [ c .. d ]");
+        assert_eq!(pretty("(if (a + b) then c else d)"), "if a + b then c else d");
+    }
+
+    const BINARY: [&str; 7] = ["=", "|>", "+", "-", "*", "/", "^"];
+    const PREFIX: [&str; 6] = ["-", "+", "~", "!", "++", "--"];
+    const POSTFIX: [&str; 5] = ["!", "++", "--", "?", "'"];
+
+    #[test]
+    fn round_trip_binary_pairs() {
+        for op1 in BINARY {
+            for op2 in BINARY {
+                if op2 != "=" {
+                    assert_round_trip(&format!("(a {op1} b) {op2} c"));
+                }
+                assert_round_trip(&format!("a {op1} (b {op2} c)"));
+                assert_round_trip(&format!("(a {op1} (b {op2} c)) ? d : (e {op1} f)"));
+            }
+        }
+    }
+
+    #[test]
+    fn round_trip_conditional() {
+        for op in BINARY {
+            if op != "=" {
+                assert_round_trip(&format!("(a {op} b) ? c : d"));
+                assert_round_trip(&format!("(a ? b : c) {op} d"));
+            }
+            assert_round_trip(&format!("a ? (b {op} c) : d"));
+            assert_round_trip(&format!("a ? b : (c {op} d)"));
+            assert_round_trip(&format!("a {op} (b ? c : d)"));
+        }
+        assert_round_trip("(a ? b : c) ? (d ? e : f) : (g ? h : i)");
+    }
+
+    #[test]
+    fn round_trip_unary() {
+        for op in BINARY {
+            for pre in PREFIX {
+                if op != "=" {
+                    assert_round_trip(&format!("({pre} a) {op} b"));
+                }
+                assert_round_trip(&format!("{pre} (a {op} b)"));
+                assert_round_trip(&format!("a {op} ({pre} b)"));
+            }
+            for post in POSTFIX {
+                if op != "=" {
+                    assert_round_trip(&format!("(a {post}) {op} b"));
+                    assert_round_trip(&format!("a {op} (b {post}) {op} c"));
+                }
+                assert_round_trip(&format!("(a {op} b) {post}"));
+                assert_round_trip(&format!("a {op} (b {post})"));
+            }
+        }
+        for pre in PREFIX {
+            for post in POSTFIX {
+                assert_round_trip(&format!("({pre} a) {post}"));
+                assert_round_trip(&format!("{pre} (a {post})"));
+                assert_round_trip(&format!("({pre} a)(b)"));
+                assert_round_trip(&format!("(a {post})(b)"));
+                assert_round_trip(&format!("(a {post}) ? b : c"));
+            }
+            for pre2 in PREFIX {
+                assert_round_trip(&format!("{pre} ({pre2} a)"));
+            }
+        }
+        for post in POSTFIX {
+            for post2 in POSTFIX {
+                assert_round_trip(&format!("(a {post}) {post2}"));
+            }
+        }
+    }
+
+    #[test]
+    fn round_trip_calls_and_mixfix() {
+        for op in BINARY {
+            assert_round_trip(&format!("f((a {op} b), ...(e {op} g), k: (c {op} d))"));
+            assert_round_trip(&format!("if (a {op} b) then (c {op} d) else (e {op} g)"));
+            assert_round_trip(&format!("a {op} (if b then c else d)"));
+            assert_round_trip(&format!("(a {op} b)[(c {op} d) .. e]"));
+            if op != "=" {
+                assert_round_trip(&format!("(if a then b else c) {op} d"));
+                assert_round_trip(&format!("(a {op} b)(c)"));
+                assert_round_trip(&format!("((a {op} if b then c else d)) {op} e"));
+            }
+        }
+    }
+}
@@ -0,0 +1,351 @@
+use crate::builder::{AstBuilder, Boxed, ExprNode};
+use crate::expression::Expr;
+use crate::lexer::Lexer;
+use crate::parselets::Name;
+use crate::parser::{BantamParser, Parser};
+use crate::token::{Span, TokenType};
+use rustc_hash::FxHashMap;
+use std::ops::Index;
+
+/// What the metavariables of a pattern matched, keyed by their names
+/// without the `$`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Bindings<'a, 'source>(FxHashMap<&'a str, &'a Expr<'source>>);
+
+impl<'a, 'source> Bindings<'a, 'source> {
+    pub fn get(&self, name: &str) -> Option<&'a Expr<'source>> {
+        self.0.get(name).copied()
+    }
+}
+
+impl<'a, 'source> Index<&str> for Bindings<'a, 'source> {
+    type Output = Expr<'source>;
+
+    fn index(&self, name: &str) -> &Self::Output {
+        self.get(name)
+            .unwrap_or_else(|| panic!("No metavariable ${name} in the pattern."))
+    }
+}
+
+type Condition<'rule> = Box<dyn Fn(&Bindings) -> bool + 'rule>;
+
+/// A rewrite rule written in Bantam itself, `pattern => replacement`, like
+/// `$x + 0 => $x`.
+///
+/// A `$name` metavariable in the pattern matches any subtree; used twice, it
+/// only matches equal subtrees, spans aside, so `$x - $x => 0` leaves `a - b`
+/// alone. Everything else matches itself. The replacement may use the
+/// pattern's metavariables, and the nodes it builds take the span of the
+/// expression they replace. The first `=>` ends the pattern, so only the
+/// replacement can contain lambdas.
+pub struct Rule<'rule> {
+    pattern: Expr<'rule>,
+    replacement: Expr<'rule>,
+    condition: Option<Condition<'rule>>,
+}
+
+impl<'rule> Rule<'rule> {
+    /// Parse `source` with the Bantam grammar. Panics if it isn't a rule or
+    /// the replacement uses a metavariable the pattern doesn't bind.
+    pub fn new(source: &'rule str) -> Self {
+        static METAVAR: Name = Name;
+        let mut grammar = BantamParser::grammar();
+        grammar.register_prefix(TokenType::Metavar, &METAVAR);
+        // the first `=>` ends the pattern, later ones are lambdas
+        let lambda = grammar.remove_infix(TokenType::FatArrow).unwrap();
+        let mut parser = Parser::with_grammar(Lexer::new(source), grammar);
+        let pattern = *parser.parse_expression();
+        parser.consume_type(TokenType::FatArrow);
+        parser.register_infix(TokenType::FatArrow, lambda);
+        let replacement = *parser.parse_expression();
+        parser.consume_type(TokenType::Eof);
+
+        let mut bound = vec![];
+        metavars(&pattern, &mut bound);
+        let mut used = vec![];
+        metavars(&replacement, &mut used);
+        if let Some(unbound) = used.iter().find(|name| !bound.contains(name)) {
+            panic!("The pattern doesn't bind the metavariable ${unbound}.");
+        }
+        Self {
+            pattern,
+            replacement,
+            condition: None,
+        }
+    }
+
+    /// Only rewrite where `condition` holds for what the metavariables
+    /// matched.
+    pub fn when(mut self, condition: impl Fn(&Bindings) -> bool + 'rule) -> Self {
+        self.condition = Some(Box::new(condition));
+        self
+    }
+
+    /// What the metavariables match if the pattern matches `expr` itself and
+    /// the condition holds.
+    pub fn matches<'a, 'source>(
+        &'a self,
+        expr: &'a Expr<'source>,
+    ) -> Option<Bindings<'a, 'source>> {
+        let mut bindings = Bindings::default();
+        if !bind(&self.pattern, expr, &mut bindings) {
+            return None;
+        }
+        match &self.condition {
+            Some(condition) if !condition(&bindings) => None,
+            _ => Some(bindings),
+        }
+    }
+
+    /// Rewrite `expr` itself, not its subtrees, if the rule matches it.
+    pub fn apply<'source>(&self, expr: &Expr<'source>) -> Option<Expr<'source>>
+    where
+        'rule: 'source,
+    {
+        let bindings = self.matches(expr)?;
+        Some(instantiate(&self.replacement, &bindings, expr.span()))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct RewriteError {
+    /// The node that was still being rewritten at the limit.
+    pub span: Span,
+    pub message: String,
+}
+
+/// Rules applied until none of them matches anywhere.
+pub struct Rules<'rule> {
+    rules: Vec<Rule<'rule>>,
+    limit: usize,
+}
+
+impl<'rule> Rules<'rule> {
+    pub fn new(rules: Vec<Rule<'rule>>) -> Self {
+        Self {
+            rules,
+            limit: 10_000,
+        }
+    }
+
+    /// Give up after `limit` rewrites rather than looping forever on rules
+    /// like `$a + $b => $b + $a`. The default is 10000.
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Rewrite `expr` to a fixpoint, innermost subtrees first and the first
+    /// matching rule in order. Fails if that takes more rewrites than the
+    /// limit.
+    pub fn rewrite<'source>(&self, expr: &Expr<'source>) -> Result<Expr<'source>, RewriteError>
+    where
+        'rule: 'source,
+    {
+        let mut steps = 0;
+        self.normalize(expr, &mut steps)
+    }
+
+    fn normalize<'source>(
+        &self,
+        expr: &Expr<'source>,
+        steps: &mut usize,
+    ) -> Result<Expr<'source>, RewriteError>
+    where
+        'rule: 'source,
+    {
+        let mut expr = self.normalize_children(expr, steps)?;
+        'rewrite: loop {
+            for rule in &self.rules {
+                if let Some(rewritten) = rule.apply(&expr) {
+                    *steps += 1;
+                    if *steps > self.limit {
+                        return Err(RewriteError {
+                            span: expr.span(),
+                            message: format!(
+                                "Rewriting didn't reach a fixpoint in {} steps.",
+                                self.limit
+                            ),
+                        });
+                    }
+                    expr = self.normalize_children(&rewritten, steps)?;
+                    continue 'rewrite;
+                }
+            }
+            return Ok(expr);
+        }
+    }
+
+    fn normalize_children<'source>(
+        &self,
+        expr: &Expr<'source>,
+        steps: &mut usize,
+    ) -> Result<Expr<'source>, RewriteError>
+    where
+        'rule: 'source,
+    {
+        // `map` can't stop early, so after an error the other children are
+        // left as they are, for the node that's thrown away
+        let mut error = None;
+        let node = ExprNode::view(expr).map(|child| {
+            let normalized = match error {
+                None => self.normalize(child, steps),
+                Some(_) => Ok(child.clone()),
+            };
+            Box::new(normalized.unwrap_or_else(|e| {
+                error = Some(e);
+                child.clone()
+            }))
+        });
+        match error {
+            Some(error) => Err(error),
+            None => Ok(*Boxed.build(node)),
+        }
+    }
+}
+
+fn metavar<'a>(expr: &'a Expr) -> Option<&'a str> {
+    match expr {
+        Expr::Name { name, .. } => name.strip_prefix('$'),
+        _ => None,
+    }
+}
+
+fn metavars<'a>(expr: &'a Expr, out: &mut Vec<&'a str>) {
+    if let Some(name) = metavar(expr) {
+        out.push(name);
+    }
+    for child in ExprNode::view(expr).children() {
+        metavars(child, out);
+    }
+}
+
+fn bind<'a, 'source>(
+    pattern: &'a Expr,
+    expr: &'a Expr<'source>,
+    bindings: &mut Bindings<'a, 'source>,
+) -> bool {
+    if let Some(name) = metavar(pattern) {
+        return match bindings.get(name) {
+            Some(bound) => bound.eq_ignore_spans(expr),
+            None => {
+                bindings.0.insert(name, expr);
+                true
+            }
+        };
+    }
+    let (pattern, expr) = (ExprNode::view(pattern), ExprNode::view(expr));
+    head(&pattern) == head(&expr)
+        && pattern
+            .children()
+            .into_iter()
+            .zip(expr.children())
+            .all(|(pattern, expr)| bind(pattern, expr, bindings))
+}
+
+// the node without its children and span, to compare operators, names and
+// argument kinds
+fn head<'source>(node: &ExprNode<'source, &Expr<'source>>) -> ExprNode<'source, ()> {
+    let mut head = node.clone().map(|_| ());
+    head.set_span(Span::default());
+    head
+}
+
+fn instantiate<'source>(
+    replacement: &Expr<'source>,
+    bindings: &Bindings<'_, 'source>,
+    span: Span,
+) -> Expr<'source> {
+    if let Some(name) = metavar(replacement) {
+        return bindings[name].clone();
+    }
+    let mut node =
+        ExprNode::view(replacement).map(|child| Box::new(instantiate(child, bindings, span)));
+    node.set_span(span);
+    *Boxed.build(node)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rewrite(rules: Vec<Rule>, source: &str) -> String {
+        let expr = BantamParser::new(source).parse_expression();
+        Rules::new(rules).rewrite(&expr).unwrap().to_string()
+    }
+
+    #[test]
+    fn rules_in_bantam_syntax() {
+        let rules = || vec![Rule::new("$x + 0 => $x"), Rule::new("$x * 1 => $x")];
+        assert_eq!(rewrite(rules(), "f(a + 0) * 1"), "f(a)");
+        assert_eq!(rewrite(rules(), "(a + 0) + 0 + b"), "(a + b)");
+        assert_eq!(rewrite(rules(), "0 + a"), "(0 + a)");
+        assert_eq!(
+            rewrite(
+                vec![Rule::new("$a * ($b + $c) => $a*$b + $a*$c")],
+                "x * (y + (z + w))"
+            ),
+            "((x * y) + ((x * z) + (x * w)))"
+        );
+    }
+
+    #[test]
+    fn lambdas_in_replacements() {
+        let rules = vec![Rule::new("twice($f) => x => $f($f(x))")];
+        assert_eq!(rewrite(rules, "twice(g) |> h"), "((x => g(g(x))) |> h)");
+    }
+
+    #[test]
+    fn repeated_metavariables_match_equal_subtrees() {
+        let rules = || vec![Rule::new("$x - $x => 0")];
+        assert_eq!(rewrite(rules(), "f(a) -  f(a)"), "0");
+        assert_eq!(rewrite(rules(), "f(a) - f(b)"), "(f(a) - f(b))");
+    }
+
+    #[test]
+    fn side_conditions() {
+        let nonzero = |bindings: &Bindings| !matches!(&bindings["x"], Expr::Number { literal, .. } if literal == "0");
+        let rules = || vec![Rule::new("$x / $x => 1").when(nonzero)];
+        assert_eq!(rewrite(rules(), "a / a"), "1");
+        assert_eq!(rewrite(rules(), "0 / 0"), "(0 / 0)");
+    }
+
+    #[test]
+    fn rewritten_nodes_keep_spans() {
+        let expr = BantamParser::new("g(-(-a))").parse_expression();
+        let rewritten = Rules::new(vec![Rule::new("-(-$x) => $x + 0")])
+            .rewrite(&expr)
+            .unwrap();
+        let Expr::Call { args, .. } = &rewritten else {
+            panic!("expected a call");
+        };
+        let Expr::Infix {
+            left, right, span, ..
+        } = args[0].value()
+        else {
+            panic!("expected a sum");
+        };
+        assert_eq!(*span, Span::new(2, 7));
+        assert_eq!(left.span(), Span::new(5, 6));
+        assert_eq!(right.span(), Span::new(2, 7));
+    }
+
+    #[test]
+    fn gives_up_on_cycles() {
+        let expr = BantamParser::new("f(a + b)").parse_expression();
+        let rules = Rules::new(vec![Rule::new("$a + $b => $b + $a")]).with_limit(5);
+        assert_eq!(
+            rules.rewrite(&expr),
+            Err(RewriteError {
+                span: Span::new(2, 7),
+                message: "Rewriting didn't reach a fixpoint in 5 steps.".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "doesn't bind the metavariable $y")]
+    fn unbound_metavariables() {
+        Rule::new("$x + 1 => $y");
+    }
+}
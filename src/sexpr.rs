@@ -0,0 +1,447 @@
+use crate::expression::{Arg, Expr};
+use crate::token::{Span, TokenType};
+use std::borrow::Cow;
+use strum::IntoEnumIterator;
+
+/// Write `expr` as an S-expression.
+///
+/// Operators head their list, `(= a (+ b (* c d)))`, prefix operators take
+/// one operand, `(- a)`, and the forms that have no operator of their own
+/// are spelled out: `(call f a (named k v) (spread xs))`, `(postfix ! a)`,
+/// `(? c t e)` and `(mixfix "if _ then _ else _" c t e)`. A function
+/// definition assigns to its signature, `(= (f x y) body)`, and a lambda
+/// is `(=> x body)`.
+pub fn write(expr: &Expr, out: &mut String) {
+    match expr {
+        Expr::Name { name, .. } => out.push_str(name),
+        Expr::Number { literal, .. } => out.push_str(literal),
+        Expr::Assign { name, right, .. } => {
+            out.push_str("(= ");
+            out.push_str(name);
+            out.push(' ');
+            write(right, out);
+            out.push(')');
+        }
+        Expr::FnDef {
+            name, params, body, ..
+        } => {
+            out.push_str("(= (");
+            out.push_str(name);
+            for param in params {
+                out.push(' ');
+                out.push_str(param);
+            }
+            out.push_str(") ");
+            write(body, out);
+            out.push(')');
+        }
+        Expr::Lambda { param, body, .. } => {
+            out.push_str("(=> ");
+            out.push_str(param);
+            out.push(' ');
+            write(body, out);
+            out.push(')');
+        }
+        Expr::Call { func, args, .. } => {
+            out.push_str("(call ");
+            write(func, out);
+            for arg in args {
+                out.push(' ');
+                match arg {
+                    Arg::Positional(value) => write(value, out),
+                    Arg::Named { name, value } => {
+                        out.push_str("(named ");
+                        out.push_str(name);
+                        out.push(' ');
+                        write(value, out);
+                        out.push(')');
+                    }
+                    Arg::Spread(value) => {
+                        out.push_str("(spread ");
+                        write(value, out);
+                        out.push(')');
+                    }
+                }
+            }
+            out.push(')');
+        }
+        Expr::Cond {
+            cond,
+            then_arm,
+            else_arm,
+            ..
+        } => {
+            out.push_str("(? ");
+            write(cond, out);
+            out.push(' ');
+            write(then_arm, out);
+            out.push(' ');
+            write(else_arm, out);
+            out.push(')');
+        }
+        Expr::Prefix { op, right, .. } => {
+            out.push('(');
+            out.push_str(op.punctuator().unwrap());
+            out.push(' ');
+            write(right, out);
+            out.push(')');
+        }
+        Expr::Postfix { left, op, .. } => {
+            out.push_str("(postfix ");
+            out.push_str(op.punctuator().unwrap());
+            out.push(' ');
+            write(left, out);
+            out.push(')');
+        }
+        Expr::Infix { left, op, right, .. } => {
+            out.push('(');
+            out.push_str(op.punctuator().unwrap());
+            out.push(' ');
+            write(left, out);
+            out.push(' ');
+            write(right, out);
+            out.push(')');
+        }
+        Expr::Pipe { left, right, .. } => {
+            out.push_str("(|> ");
+            write(left, out);
+            out.push(' ');
+            write(right, out);
+            out.push(')');
+        }
+        Expr::Mixfix { pattern, operands, .. } => {
+            out.push_str("(mixfix \"");
+            out.push_str(pattern);
+            out.push('"');
+            for operand in operands {
+                out.push(' ');
+                write(operand, out);
+            }
+            out.push(')');
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ReadError {
+    pub offset: usize,
+    pub message: String,
+}
+
+/// Read an S-expression in the form produced by `write` back into an
+/// `Expr`, borrowing names from `input`.
+pub fn read(input: &str) -> Result<Box<Expr<'_>>, ReadError> {
+    let mut reader = Reader { input, pos: 0 };
+    let expr = reader.expr()?;
+    match reader.next()? {
+        None => Ok(expr),
+        Some((offset, _)) => Err(reader.error(offset, "Unexpected input after expression.")),
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Tok<'source> {
+    Open,
+    Close,
+    Atom(&'source str),
+    Str(&'source str),
+}
+
+struct Reader<'source> {
+    input: &'source str,
+    pos: usize,
+}
+
+impl<'source> Reader<'source> {
+    fn error(&self, offset: usize, message: &str) -> ReadError {
+        ReadError {
+            offset,
+            message: message.to_string(),
+        }
+    }
+
+    fn next(&mut self) -> Result<Option<(usize, Tok<'source>)>, ReadError> {
+        let rest = &self.input[self.pos..];
+        let skipped = rest.len() - rest.trim_start().len();
+        self.pos += skipped;
+        let start = self.pos;
+        let rest = &self.input[start..];
+        let Some(c) = rest.chars().next() else {
+            return Ok(None);
+        };
+        let tok = match c {
+            '(' => {
+                self.pos += 1;
+                Tok::Open
+            }
+            ')' => {
+                self.pos += 1;
+                Tok::Close
+            }
+            '"' => {
+                let Some(len) = rest[1..].find('"') else {
+                    return Err(self.error(start, "Unterminated string."));
+                };
+                self.pos += len + 2;
+                Tok::Str(&rest[1..len + 1])
+            }
+            _ => {
+                let len = rest
+                    .find(|c: char| c.is_whitespace() || c == '(' || c == ')' || c == '"')
+                    .unwrap_or(rest.len());
+                self.pos += len;
+                Tok::Atom(&rest[..len])
+            }
+        };
+        Ok(Some((start, tok)))
+    }
+
+    fn expect_next(&mut self) -> Result<(usize, Tok<'source>), ReadError> {
+        self.next()?
+            .ok_or_else(|| self.error(self.input.len(), "Unexpected end of input."))
+    }
+
+    fn expect_close(&mut self) -> Result<(), ReadError> {
+        match self.expect_next()? {
+            (_, Tok::Close) => Ok(()),
+            (offset, _) => Err(self.error(offset, "Expected ')'.")),
+        }
+    }
+
+    // read the `)` of a list opened at `open` and return the list's span
+    fn close(&mut self, open: usize) -> Result<Span, ReadError> {
+        self.expect_close()?;
+        Ok(Span::new(open, self.pos))
+    }
+
+    fn name(&mut self) -> Result<&'source str, ReadError> {
+        match self.expect_next()? {
+            (_, Tok::Atom(atom)) if atom.chars().all(char::is_alphabetic) => Ok(atom),
+            (offset, _) => Err(self.error(offset, "Expected a name.")),
+        }
+    }
+
+    fn operator(&mut self) -> Result<TokenType, ReadError> {
+        match self.expect_next()? {
+            (offset, Tok::Atom(atom)) => {
+                operator(atom).ok_or_else(|| self.error(offset, "Unknown operator."))
+            }
+            (offset, _) => Err(self.error(offset, "Expected an operator.")),
+        }
+    }
+
+    fn expr(&mut self) -> Result<Box<Expr<'source>>, ReadError> {
+        let (offset, tok) = self.expect_next()?;
+        self.expr_from(offset, tok)
+    }
+
+    fn expr_from(&mut self, offset: usize, tok: Tok<'source>) -> Result<Box<Expr<'source>>, ReadError> {
+        let span = Span::new(offset, self.pos);
+        match tok {
+            Tok::Atom(atom) if atom.starts_with(|c: char| c.is_ascii_digit()) => {
+                Ok(Box::new(Expr::Number { literal: Cow::Borrowed(atom), span }))
+            }
+            Tok::Atom(atom) if atom.chars().all(char::is_alphabetic) => {
+                Ok(Box::new(Expr::Name { name: Cow::Borrowed(atom), span }))
+            }
+            Tok::Open => self.list(offset),
+            _ => Err(self.error(offset, "Expected an expression.")),
+        }
+    }
+
+    // the rest of a list whose `(` has been read at `open`
+    fn list(&mut self, open: usize) -> Result<Box<Expr<'source>>, ReadError> {
+        let (offset, head) = self.expect_next()?;
+        let Tok::Atom(head) = head else {
+            return Err(self.error(offset, "Expected an operator or form name."));
+        };
+        let expr = match head {
+            "call" => {
+                let func = self.expr()?;
+                let mut args = vec![];
+                loop {
+                    match self.expect_next()? {
+                        (_, Tok::Close) => break,
+                        (offset, tok) => args.push(self.arg(offset, tok)?),
+                    }
+                }
+                let span = Span::new(open, self.pos);
+                Expr::Call { func, args, span }
+            }
+            "postfix" => Expr::Postfix {
+                op: self.operator()?,
+                left: self.expr()?,
+                span: self.close(open)?,
+            },
+            "mixfix" => {
+                let pattern = match self.expect_next()? {
+                    (_, Tok::Str(pattern)) => Cow::Borrowed(pattern),
+                    (offset, _) => return Err(self.error(offset, "Expected a mixfix pattern.")),
+                };
+                let mut operands = vec![];
+                loop {
+                    match self.expect_next()? {
+                        (_, Tok::Close) => break,
+                        (offset, tok) => operands.push(*self.expr_from(offset, tok)?),
+                    }
+                }
+                let span = Span::new(open, self.pos);
+                Expr::Mixfix { pattern, operands, span }
+            }
+            "?" => Expr::Cond {
+                cond: self.expr()?,
+                then_arm: self.expr()?,
+                else_arm: self.expr()?,
+                span: self.close(open)?,
+            },
+            "=" => match self.expect_next()? {
+                (_, Tok::Open) => {
+                    let name = Cow::Borrowed(self.name()?);
+                    let mut params = vec![];
+                    loop {
+                        match self.expect_next()? {
+                            (_, Tok::Close) => break,
+                            (_, Tok::Atom(atom)) if atom.chars().all(char::is_alphabetic) => {
+                                params.push(Cow::Borrowed(atom))
+                            }
+                            (offset, _) => return Err(self.error(offset, "Expected a name.")),
+                        }
+                    }
+                    Expr::FnDef {
+                        name,
+                        params,
+                        body: self.expr()?,
+                        span: self.close(open)?,
+                    }
+                }
+                (_, Tok::Atom(atom)) if atom.chars().all(char::is_alphabetic) => Expr::Assign {
+                    name: Cow::Borrowed(atom),
+                    right: self.expr()?,
+                    span: self.close(open)?,
+                },
+                (offset, _) => return Err(self.error(offset, "Expected a name.")),
+            },
+            "=>" => Expr::Lambda {
+                param: Cow::Borrowed(self.name()?),
+                body: self.expr()?,
+                span: self.close(open)?,
+            },
+            "|>" => Expr::Pipe {
+                left: self.expr()?,
+                right: self.expr()?,
+                span: self.close(open)?,
+            },
+            _ => {
+                let op = operator(head).ok_or_else(|| self.error(offset, "Unknown operator."))?;
+                let operand = self.expr()?;
+                match self.expect_next()? {
+                    (_, Tok::Close) => Expr::Prefix {
+                        op,
+                        right: operand,
+                        span: Span::new(open, self.pos),
+                    },
+                    (offset, tok) => Expr::Infix {
+                        left: operand,
+                        op,
+                        right: self.expr_from(offset, tok)?,
+                        span: self.close(open)?,
+                    },
+                }
+            }
+        };
+        Ok(Box::new(expr))
+    }
+
+    fn arg(&mut self, offset: usize, tok: Tok<'source>) -> Result<Arg<'source>, ReadError> {
+        if tok == Tok::Open {
+            let rest = self.input[self.pos..].trim_start();
+            let form = |name: &str| {
+                rest.strip_prefix(name)
+                    .is_some_and(|after| after.starts_with(char::is_whitespace))
+            };
+            if form("named") {
+                self.next()?;
+                let name = Cow::Borrowed(self.name()?);
+                let value = *self.expr()?;
+                self.expect_close()?;
+                return Ok(Arg::Named { name, value });
+            }
+            if form("spread") {
+                self.next()?;
+                let value = *self.expr()?;
+                self.expect_close()?;
+                return Ok(Arg::Spread(value));
+            }
+        }
+        Ok(Arg::Positional(*self.expr_from(offset, tok)?))
+    }
+}
+
+fn operator(atom: &str) -> Option<TokenType> {
+    TokenType::iter().find(|tt| tt.punctuator() == Some(atom))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::IF;
+    use crate::parser::BantamParser;
+
+    fn sexpr(source: &str) -> String {
+        let mut parser = BantamParser::new(source);
+        parser.register_mixfix(&IF);
+        let mut out = String::new();
+        write(&parser.parse_expression(), &mut out);
+        out
+    }
+
+    fn assert_round_trip(source: &str) {
+        let mut parser = BantamParser::new(source);
+        parser.register_mixfix(&IF);
+        let expr = parser.parse_expression();
+        let mut out = String::new();
+        write(&expr, &mut out);
+        assert_eq!(read(&out).unwrap().to_string(), expr.to_string(), "{out}");
+    }
+
+    #[test]
+    fn write_forms() {
+        assert_eq!(sexpr("a = b + c * d"), "(= a (+ b (* c d)))");
+        assert_eq!(sexpr("f(a, b)"), "(call f a b)");
+        assert_eq!(sexpr("c ? t : e"), "(? c t e)");
+        assert_eq!(sexpr("-a! + 2.5"), "(+ (- (postfix ! a)) 2.5)");
+        assert_eq!(sexpr("f(a, ...b, k: c)()"), "(call (call f a (spread b) (named k c)))");
+        assert_eq!(sexpr("a |> f"), "(|> a f)");
+        assert_eq!(sexpr("f(x, y) = x * y"), "(= (f x y) (* x y))");
+        assert_eq!(sexpr("x => y => x"), "(=> x (=> y x))");
+        assert_eq!(sexpr("if a then b else c"), "(mixfix \"if _ then _ else _\" a b c)");
+    }
+
+    #[test]
+    fn read_write_round_trip() {
+        assert_round_trip("a = b + c * d ^ e - f / g");
+        assert_round_trip("~ ! - + a ! ++ -- ? '");
+        assert_round_trip("f(a ? b : c, ...d, k: -e, call: f)(g)");
+        assert_round_trip("a |> f(b) |> g");
+        assert_round_trip("if a then f(b) else c ? d : e");
+        assert_round_trip("named(spread, postfix)");
+        assert_round_trip("f(x) = g() = x ? f(x - 1) : 1");
+        assert_round_trip("f = x => g(y => x + y, x => x)");
+    }
+
+    #[test]
+    fn read_errors() {
+        let error = |input| match read(input) {
+            Err(error) => error,
+            Ok(_) => panic!("{input} should not read"),
+        };
+        assert_eq!(error("(+ a b").offset, 6);
+        assert_eq!(error("(+ a b))").message, "Unexpected input after expression.");
+        assert_eq!(error("(% a b)").message, "Unknown operator.");
+        assert_eq!(error("(= (+ a) b)").message, "Expected a name.");
+        assert_eq!(error("(= (f 1) b)").message, "Expected a name.");
+        assert_eq!(error("(- a b c)").message, "Expected ')'.");
+        assert_eq!(error("(mixfix \"_ ? _ : _ a)").message, "Unterminated string.");
+        assert_eq!(error(")").offset, 0);
+    }
+}
@@ -0,0 +1,308 @@
+use crate::eval::Value;
+use crate::expression::Expr;
+use crate::number::Number;
+use crate::operators::{Fixity, OperatorTable};
+use crate::token::{Span, TokenType};
+use crate::visit::Fold;
+use std::borrow::Cow;
+
+/// Simplify `expr` with the identities that hold for every number, see
+/// `Simplifier`.
+pub fn simplify(expr: Expr) -> Expr {
+    Simplifier::new().simplify(expr)
+}
+
+/// Folds constant subtrees and applies algebraic identities, so that the
+/// result evaluates like the original with the standard operators.
+///
+/// Operators on numbers are computed as `eval` would, unless that's an
+/// error, like `1 / 0`, which is left for `eval` to report. A conditional
+/// with a constant condition becomes the arm it takes. `x + 0`, `0 + x`,
+/// `x - 0`, `x * 1`, `1 * x`, `x / 1`, `x ^ 1` and `-(-x)` become `x`, for
+/// the integers `0` and `1`, since `x + 0.0` is a float even if `x` isn't.
+///
+/// A folded constant takes the span of the expression it replaces, and an
+/// expression that simplifies to one of its operands keeps the operand's
+/// span, so errors still point at the source.
+#[derive(Debug, Clone, Default)]
+pub struct Simplifier {
+    operators: OperatorTable,
+    zero_products: bool,
+}
+
+impl Simplifier {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Also simplify `x * 0` and `0 * x` to `0`. That changes the result
+    /// when `x` is an infinite or NaN float or the zero is a float, and
+    /// drops whatever `x` assigns or fails with.
+    pub fn with_zero_products(mut self) -> Self {
+        self.zero_products = true;
+        self
+    }
+
+    pub fn simplify<'source>(&mut self, expr: Expr<'source>) -> Expr<'source> {
+        self.fold_expr(expr)
+    }
+
+    fn apply_unary(&self, op: TokenType, fixity: Fixity, operand: &Expr) -> Option<Number> {
+        let operand = Value::Number(constant(operand)?);
+        number(self.operators.apply_unary(op, fixity, &operand)?)
+    }
+
+    fn apply_infix(&self, op: TokenType, left: &Expr, right: &Expr) -> Option<Number> {
+        let left = Value::Number(constant(left)?);
+        let right = Value::Number(constant(right)?);
+        number(self.operators.apply_infix(op, &left, &right)?)
+    }
+}
+
+impl<'source> Fold<'source> for Simplifier {
+    fn fold_cond(
+        &mut self,
+        cond: Box<Expr<'source>>,
+        then_arm: Box<Expr<'source>>,
+        else_arm: Box<Expr<'source>>,
+        span: Span,
+    ) -> Expr<'source> {
+        let cond = self.fold_boxed(cond);
+        match constant(&cond) {
+            Some(n) if n.is_zero() => self.fold_expr(*else_arm),
+            Some(_) => self.fold_expr(*then_arm),
+            None => Expr::Cond {
+                cond,
+                then_arm: self.fold_boxed(then_arm),
+                else_arm: self.fold_boxed(else_arm),
+                span,
+            },
+        }
+    }
+
+    fn fold_prefix(
+        &mut self,
+        op: TokenType,
+        right: Box<Expr<'source>>,
+        span: Span,
+    ) -> Expr<'source> {
+        let right = self.fold_boxed(right);
+        if let Some(folded) = self
+            .apply_unary(op, Fixity::Prefix, &right)
+            .and_then(|n| literal(&n, span))
+        {
+            return folded;
+        }
+        match (op, *right) {
+            (
+                TokenType::Minus,
+                Expr::Prefix {
+                    op: TokenType::Minus,
+                    right,
+                    ..
+                },
+            ) => *right,
+            (op, right) => Expr::Prefix {
+                op,
+                right: Box::new(right),
+                span,
+            },
+        }
+    }
+
+    fn fold_postfix(
+        &mut self,
+        left: Box<Expr<'source>>,
+        op: TokenType,
+        span: Span,
+    ) -> Expr<'source> {
+        let left = self.fold_boxed(left);
+        if let Some(folded) = self
+            .apply_unary(op, Fixity::Postfix, &left)
+            .and_then(|n| literal(&n, span))
+        {
+            return folded;
+        }
+        Expr::Postfix { left, op, span }
+    }
+
+    fn fold_infix(
+        &mut self,
+        left: Box<Expr<'source>>,
+        op: TokenType,
+        right: Box<Expr<'source>>,
+        span: Span,
+    ) -> Expr<'source> {
+        let (left, right) = (self.fold_boxed(left), self.fold_boxed(right));
+        if let Some(folded) = self
+            .apply_infix(op, &left, &right)
+            .and_then(|n| literal(&n, span))
+        {
+            return folded;
+        }
+        let is = |expr: &Expr, n: i64| constant(expr) == Some(Number::Int(n));
+        let zero = |expr: &Expr| constant(expr).is_some_and(|n| n.is_zero());
+        match op {
+            TokenType::Plus if is(&right, 0) => *left,
+            TokenType::Plus if is(&left, 0) => *right,
+            TokenType::Minus if is(&right, 0) => *left,
+            TokenType::Asterisk if is(&right, 1) => *left,
+            TokenType::Asterisk if is(&left, 1) => *right,
+            TokenType::Asterisk if self.zero_products && zero(&left) => {
+                let mut zero = *left;
+                zero.set_span(span);
+                zero
+            }
+            TokenType::Asterisk if self.zero_products && zero(&right) => {
+                let mut zero = *right;
+                zero.set_span(span);
+                zero
+            }
+            TokenType::Slash | TokenType::Caret if is(&right, 1) => *left,
+            _ => Expr::Infix {
+                left,
+                op,
+                right,
+                span,
+            },
+        }
+    }
+}
+
+// the number `expr` is a literal for, maybe negated
+fn constant(expr: &Expr) -> Option<Number> {
+    match expr {
+        Expr::Number { literal, .. } => Number::parse(literal),
+        Expr::Prefix {
+            op: TokenType::Minus,
+            right,
+            ..
+        } => match &**right {
+            Expr::Number { literal, .. } => Number::parse(literal).map(|n| -&n),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn number(result: Result<Value, String>) -> Option<Number> {
+    match result {
+        Ok(Value::Number(n)) => Some(n),
+        _ => None,
+    }
+}
+
+// `n` as a literal, negated if it's negative, or `None` if no literal reads
+// as it, like for infinity or `1e21`
+fn literal<'source>(n: &Number, span: Span) -> Option<Expr<'source>> {
+    let negative = match n {
+        Number::Int(n) => *n < 0,
+        Number::Big(n) => n.is_negative(),
+        Number::Float(x) => x.is_sign_negative(),
+    };
+    let magnitude = if negative { -n } else { n.clone() };
+    let digits = magnitude.to_string();
+    if !digits.chars().all(|c| c.is_ascii_digit() || c == '.') {
+        return None;
+    }
+    let literal = Expr::Number {
+        literal: Cow::Owned(digits),
+        span,
+    };
+    Some(if negative {
+        Expr::Prefix {
+            op: TokenType::Minus,
+            right: Box::new(literal),
+            span,
+        }
+    } else {
+        literal
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::{eval, Env};
+    use crate::parser::BantamParser;
+
+    fn simplified(source: &str) -> String {
+        simplify(*BantamParser::new(source).parse_expression()).to_string()
+    }
+
+    #[test]
+    fn constants() {
+        assert_eq!(simplified("1 + 2 * 3"), "7");
+        assert_eq!(simplified("a + 2 ^ 3 - 10"), "((a + 8) - 10)");
+        assert_eq!(simplified("f(6 / 4, 3!)"), "f(1.5, 6)");
+        assert_eq!(simplified("1 - 3 + x"), "((-2) + x)");
+        assert_eq!(simplified("-(1 - 3)"), "2");
+        assert_eq!(simplified("2 ^ 70"), "1180591620717411303424");
+        // errors and numbers without a literal are left to `eval`
+        assert_eq!(simplified("1 / 0 + 1"), "((1 / 0) + 1)");
+        assert_eq!(simplified("2.0 ^ 100"), "(2.0 ^ 100)");
+    }
+
+    #[test]
+    fn identities() {
+        assert_eq!(simplified("(x + 0) * 1 - 0"), "x");
+        assert_eq!(simplified("1 * (0 + f(x)) / 1"), "f(x)");
+        assert_eq!(simplified("x ^ (3 - 2)"), "x");
+        assert_eq!(simplified("-(-x) + -x"), "(x + (-x))");
+        assert_eq!(simplified("x + 0.0"), "(x + 0.0)");
+        assert_eq!(simplified("x * 0"), "(x * 0)");
+    }
+
+    #[test]
+    fn zero_products() {
+        let simplify = |source: &str| {
+            let expr = *BantamParser::new(source).parse_expression();
+            Simplifier::new()
+                .with_zero_products()
+                .simplify(expr)
+                .to_string()
+        };
+        assert_eq!(simplify("y + x * 0"), "y");
+        assert_eq!(simplify("0.0 * f(x) + 1"), "1.0");
+    }
+
+    #[test]
+    fn conditionals() {
+        assert_eq!(simplified("1 - 1 ? a : b + 0"), "b");
+        assert_eq!(simplified("2 ? a * 1 : b"), "a");
+        assert_eq!(simplified("c ? 1 + 1 : 3"), "(c ? 2 : 3)");
+    }
+
+    #[test]
+    fn spans() {
+        let source = "(a * 1) + (2 + 3)";
+        let expr = simplify(*BantamParser::new(source).parse_expression());
+        let Expr::Infix { left, right, .. } = &expr else {
+            panic!("expected an infix expression, got {expr:?}");
+        };
+        assert_eq!(&source[left.span().start..left.span().end], "a");
+        assert_eq!(&source[right.span().start..right.span().end], "(2 + 3)");
+    }
+
+    #[test]
+    fn same_values() {
+        for source in [
+            "x * 1 + 2 * 3 - 0",
+            "-(-x) ^ 1",
+            "x / 1 ? 4 / 2 : 0",
+            "0 + x - 3!",
+        ] {
+            let expr = *BantamParser::new(source).parse_expression();
+            for x in [Value::from(3), Value::from(-0.5), Value::from(0)] {
+                let (mut env, mut simplified_env) = (Env::new(), Env::new());
+                env.set("x", x.clone());
+                simplified_env.set("x", x);
+                assert_eq!(
+                    eval(&simplify(expr.clone()), &mut simplified_env),
+                    eval(&expr, &mut env),
+                    "{source}"
+                );
+            }
+        }
+    }
+}
@@ -1,6 +1,10 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 use strum_macros::EnumIter;
 
 #[derive(EnumIter, Debug, Copy, Clone,  PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum TokenType {
     LeftParen,
     RightParen,
@@ -15,36 +19,83 @@ pub enum TokenType {
     Bang,
     Question,
     Colon,
+    Pipe,
+    LeftBracket,
+    RightBracket,
+    DotDot,
+    Ellipsis,
+    PlusPlus,
+    MinusMinus,
+    Apostrophe,
+    FatArrow,
     Name,
+    Metavar,
+    Number,
+    Keyword,
     Eof,
 }
 
 impl TokenType {
-    pub fn punctuator(&self) -> Option<char> {
+    pub fn punctuator(&self) -> Option<&'static str> {
         match *self {
-            Self::LeftParen => Some('('),
-            Self::RightParen => Some(')'),
-            Self::Comma => Some(','),
-            Self::Assign => Some('='),
-            Self::Plus => Some('+'),
-            Self::Minus => Some('-'),
-            Self::Asterisk => Some('*'),
-            Self::Slash => Some('/'),
-            Self::Caret => Some('^'),
-            Self::Tilde => Some('~'),
-            Self::Bang => Some('!'),
-            Self::Question => Some('?'),
-            Self::Colon => Some(':'),
-            Self::Eof | Self::Name => None,
+            Self::LeftParen => Some("("),
+            Self::RightParen => Some(")"),
+            Self::Comma => Some(","),
+            Self::Assign => Some("="),
+            Self::Plus => Some("+"),
+            Self::Minus => Some("-"),
+            Self::Asterisk => Some("*"),
+            Self::Slash => Some("/"),
+            Self::Caret => Some("^"),
+            Self::Tilde => Some("~"),
+            Self::Bang => Some("!"),
+            Self::Question => Some("?"),
+            Self::Colon => Some(":"),
+            Self::Pipe => Some("|>"),
+            Self::LeftBracket => Some("["),
+            Self::RightBracket => Some("]"),
+            Self::DotDot => Some(".."),
+            Self::Ellipsis => Some("..."),
+            Self::PlusPlus => Some("++"),
+            Self::MinusMinus => Some("--"),
+            Self::Apostrophe => Some("'"),
+            Self::FatArrow => Some("=>"),
+            Self::Eof | Self::Name | Self::Metavar | Self::Number | Self::Keyword => None,
         }
     }
 }
 
 
-#[derive(Debug, Copy, Clone, PartialEq)]
+/// Byte offsets `start..end` into the source a token or expression was
+/// read from. Nodes built by hand rather than parsed carry `Span::default()`.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    /// The span from the start of `self` to the end of `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span::new(self.start, other.end)
+    }
+}
+
+/// The lexer always borrows `literal` from the source. It is only owned in
+/// tokens deserialized from JSON that can't be borrowed, like escaped strings
+/// or a reader's buffer.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Token<'source> {
-    pub literal: &'source str,
+    #[cfg_attr(feature = "serde", serde(borrow))]
+    pub literal: Cow<'source, str>,
     pub typ: TokenType,
+    pub span: Span,
 }
 
 
@@ -0,0 +1,417 @@
+use crate::expression::{Arg, Expr};
+use crate::token::{Span, TokenType};
+use std::borrow::Cow;
+
+/// Read-only walk over an `Expr`.
+///
+/// `visit_expr` calls `enter`, then the `visit_*` method for the variant,
+/// then `leave`. Every `visit_*` method visits the children by default, so
+/// an analysis only overrides the nodes it is interested in.
+pub trait Visitor<'source> {
+    fn enter(&mut self, _expr: &Expr<'source>) {}
+
+    fn leave(&mut self, _expr: &Expr<'source>) {}
+
+    fn visit_expr(&mut self, expr: &Expr<'source>) {
+        self.enter(expr);
+        walk_expr(self, expr);
+        self.leave(expr);
+    }
+
+    fn visit_name(&mut self, _name: &str) {}
+
+    fn visit_number(&mut self, _literal: &str) {}
+
+    fn visit_assign(&mut self, _name: &str, right: &Expr<'source>) {
+        self.visit_expr(right);
+    }
+
+    fn visit_fn_def(&mut self, _name: &str, _params: &[Cow<'source, str>], body: &Expr<'source>) {
+        self.visit_expr(body);
+    }
+
+    fn visit_lambda(&mut self, _param: &str, body: &Expr<'source>) {
+        self.visit_expr(body);
+    }
+
+    fn visit_call(&mut self, func: &Expr<'source>, args: &[Arg<'source>]) {
+        self.visit_expr(func);
+        for arg in args {
+            self.visit_arg(arg);
+        }
+    }
+
+    fn visit_arg(&mut self, arg: &Arg<'source>) {
+        match arg {
+            Arg::Positional(value) | Arg::Named { value, .. } | Arg::Spread(value) => {
+                self.visit_expr(value)
+            }
+        }
+    }
+
+    fn visit_cond(
+        &mut self,
+        cond: &Expr<'source>,
+        then_arm: &Expr<'source>,
+        else_arm: &Expr<'source>,
+    ) {
+        self.visit_expr(cond);
+        self.visit_expr(then_arm);
+        self.visit_expr(else_arm);
+    }
+
+    fn visit_prefix(&mut self, _op: TokenType, right: &Expr<'source>) {
+        self.visit_expr(right);
+    }
+
+    fn visit_postfix(&mut self, left: &Expr<'source>, _op: TokenType) {
+        self.visit_expr(left);
+    }
+
+    fn visit_infix(&mut self, left: &Expr<'source>, _op: TokenType, right: &Expr<'source>) {
+        self.visit_expr(left);
+        self.visit_expr(right);
+    }
+
+    fn visit_pipe(&mut self, left: &Expr<'source>, right: &Expr<'source>) {
+        self.visit_expr(left);
+        self.visit_expr(right);
+    }
+
+    fn visit_mixfix(&mut self, _pattern: &str, operands: &[Expr<'source>]) {
+        for operand in operands {
+            self.visit_expr(operand);
+        }
+    }
+}
+
+/// Dispatch `expr` to the `visit_*` method of its variant.
+pub fn walk_expr<'source, V: Visitor<'source> + ?Sized>(visitor: &mut V, expr: &Expr<'source>) {
+    match expr {
+        Expr::Name { name, .. } => visitor.visit_name(name),
+        Expr::Number { literal, .. } => visitor.visit_number(literal),
+        Expr::Assign { name, right, .. } => visitor.visit_assign(name, right),
+        Expr::FnDef {
+            name, params, body, ..
+        } => visitor.visit_fn_def(name, params, body),
+        Expr::Lambda { param, body, .. } => visitor.visit_lambda(param, body),
+        Expr::Call { func, args, .. } => visitor.visit_call(func, args),
+        Expr::Cond {
+            cond,
+            then_arm,
+            else_arm,
+            ..
+        } => visitor.visit_cond(cond, then_arm, else_arm),
+        Expr::Prefix { op, right, .. } => visitor.visit_prefix(*op, right),
+        Expr::Postfix { left, op, .. } => visitor.visit_postfix(left, *op),
+        Expr::Infix { left, op, right, .. } => visitor.visit_infix(left, *op, right),
+        Expr::Pipe { left, right, .. } => visitor.visit_pipe(left, right),
+        Expr::Mixfix { pattern, operands, .. } => visitor.visit_mixfix(pattern, operands),
+    }
+}
+
+/// Rewrite an `Expr` by value, bottom up.
+///
+/// Every `fold_*` method folds the children and rebuilds its variant by
+/// default, reusing the existing boxes and keeping the span, so a
+/// transformation only overrides the nodes it rewrites. An override that
+/// wants its children folded too calls `fold_boxed` or `fold_expr` on them
+/// itself.
+pub trait Fold<'source> {
+    fn fold_expr(&mut self, expr: Expr<'source>) -> Expr<'source> {
+        match expr {
+            Expr::Name { name, span } => self.fold_name(name, span),
+            Expr::Number { literal, span } => self.fold_number(literal, span),
+            Expr::Assign { name, right, span } => self.fold_assign(name, right, span),
+            Expr::FnDef {
+                name,
+                params,
+                body,
+                span,
+            } => self.fold_fn_def(name, params, body, span),
+            Expr::Lambda { param, body, span } => self.fold_lambda(param, body, span),
+            Expr::Call { func, args, span } => self.fold_call(func, args, span),
+            Expr::Cond {
+                cond,
+                then_arm,
+                else_arm,
+                span,
+            } => self.fold_cond(cond, then_arm, else_arm, span),
+            Expr::Prefix { op, right, span } => self.fold_prefix(op, right, span),
+            Expr::Postfix { left, op, span } => self.fold_postfix(left, op, span),
+            Expr::Infix {
+                left,
+                op,
+                right,
+                span,
+            } => self.fold_infix(left, op, right, span),
+            Expr::Pipe { left, right, span } => self.fold_pipe(left, right, span),
+            Expr::Mixfix {
+                pattern,
+                operands,
+                span,
+            } => self.fold_mixfix(pattern, operands, span),
+        }
+    }
+
+    /// Fold the expression inside `expr` without reallocating the box.
+    fn fold_boxed(&mut self, mut expr: Box<Expr<'source>>) -> Box<Expr<'source>> {
+        let placeholder = Expr::Name {
+            name: Cow::Borrowed(""),
+            span: Span::default(),
+        };
+        let inner = std::mem::replace(&mut *expr, placeholder);
+        *expr = self.fold_expr(inner);
+        expr
+    }
+
+    fn fold_name(&mut self, name: Cow<'source, str>, span: Span) -> Expr<'source> {
+        Expr::Name { name, span }
+    }
+
+    fn fold_number(&mut self, literal: Cow<'source, str>, span: Span) -> Expr<'source> {
+        Expr::Number { literal, span }
+    }
+
+    fn fold_assign(
+        &mut self,
+        name: Cow<'source, str>,
+        right: Box<Expr<'source>>,
+        span: Span,
+    ) -> Expr<'source> {
+        Expr::Assign {
+            name,
+            right: self.fold_boxed(right),
+            span,
+        }
+    }
+
+    fn fold_fn_def(
+        &mut self,
+        name: Cow<'source, str>,
+        params: Vec<Cow<'source, str>>,
+        body: Box<Expr<'source>>,
+        span: Span,
+    ) -> Expr<'source> {
+        Expr::FnDef {
+            name,
+            params,
+            body: self.fold_boxed(body),
+            span,
+        }
+    }
+
+    fn fold_lambda(
+        &mut self,
+        param: Cow<'source, str>,
+        body: Box<Expr<'source>>,
+        span: Span,
+    ) -> Expr<'source> {
+        Expr::Lambda {
+            param,
+            body: self.fold_boxed(body),
+            span,
+        }
+    }
+
+    fn fold_call(
+        &mut self,
+        func: Box<Expr<'source>>,
+        args: Vec<Arg<'source>>,
+        span: Span,
+    ) -> Expr<'source> {
+        Expr::Call {
+            func: self.fold_boxed(func),
+            args: args.into_iter().map(|arg| self.fold_arg(arg)).collect(),
+            span,
+        }
+    }
+
+    fn fold_arg(&mut self, arg: Arg<'source>) -> Arg<'source> {
+        match arg {
+            Arg::Positional(value) => Arg::Positional(self.fold_expr(value)),
+            Arg::Named { name, value } => Arg::Named {
+                name,
+                value: self.fold_expr(value),
+            },
+            Arg::Spread(value) => Arg::Spread(self.fold_expr(value)),
+        }
+    }
+
+    fn fold_cond(
+        &mut self,
+        cond: Box<Expr<'source>>,
+        then_arm: Box<Expr<'source>>,
+        else_arm: Box<Expr<'source>>,
+        span: Span,
+    ) -> Expr<'source> {
+        Expr::Cond {
+            cond: self.fold_boxed(cond),
+            then_arm: self.fold_boxed(then_arm),
+            else_arm: self.fold_boxed(else_arm),
+            span,
+        }
+    }
+
+    fn fold_prefix(&mut self, op: TokenType, right: Box<Expr<'source>>, span: Span) -> Expr<'source> {
+        Expr::Prefix {
+            op,
+            right: self.fold_boxed(right),
+            span,
+        }
+    }
+
+    fn fold_postfix(&mut self, left: Box<Expr<'source>>, op: TokenType, span: Span) -> Expr<'source> {
+        Expr::Postfix {
+            left: self.fold_boxed(left),
+            op,
+            span,
+        }
+    }
+
+    fn fold_infix(
+        &mut self,
+        left: Box<Expr<'source>>,
+        op: TokenType,
+        right: Box<Expr<'source>>,
+        span: Span,
+    ) -> Expr<'source> {
+        Expr::Infix {
+            left: self.fold_boxed(left),
+            op,
+            right: self.fold_boxed(right),
+            span,
+        }
+    }
+
+    fn fold_pipe(
+        &mut self,
+        left: Box<Expr<'source>>,
+        right: Box<Expr<'source>>,
+        span: Span,
+    ) -> Expr<'source> {
+        Expr::Pipe {
+            left: self.fold_boxed(left),
+            right: self.fold_boxed(right),
+            span,
+        }
+    }
+
+    fn fold_mixfix(
+        &mut self,
+        pattern: Cow<'source, str>,
+        operands: Vec<Expr<'source>>,
+        span: Span,
+    ) -> Expr<'source> {
+        Expr::Mixfix {
+            pattern,
+            operands: operands
+                .into_iter()
+                .map(|operand| self.fold_expr(operand))
+                .collect(),
+            span,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::BantamParser;
+
+    #[derive(Default)]
+    struct Names(Vec<String>);
+
+    impl<'source> Visitor<'source> for Names {
+        fn visit_name(&mut self, name: &str) {
+            self.0.push(name.to_string());
+        }
+
+        fn visit_assign(&mut self, name: &str, right: &Expr<'source>) {
+            self.0.push(name.to_string());
+            self.visit_expr(right);
+        }
+    }
+
+    #[derive(Default)]
+    struct Depth {
+        current: usize,
+        max: usize,
+    }
+
+    impl<'source> Visitor<'source> for Depth {
+        fn enter(&mut self, _expr: &Expr<'source>) {
+            self.current += 1;
+            self.max = self.max.max(self.current);
+        }
+
+        fn leave(&mut self, _expr: &Expr<'source>) {
+            self.current -= 1;
+        }
+    }
+
+    #[test]
+    fn visit_every_variant() {
+        let mut parser = BantamParser::new("x = f(a, ...g, b: c ? d : -e!) |> h + 1");
+        let expr = parser.parse_expression();
+        let mut names = Names::default();
+        names.visit_expr(&expr);
+        assert_eq!(names.0, ["x", "f", "a", "g", "c", "d", "e", "h"]);
+    }
+
+    #[test]
+    fn enter_and_leave() {
+        let mut parser = BantamParser::new("a + b * -c");
+        let expr = parser.parse_expression();
+        let mut depth = Depth::default();
+        depth.visit_expr(&expr);
+        assert_eq!(depth.current, 0);
+        assert_eq!(depth.max, 4);
+    }
+
+    struct Rename;
+
+    impl<'source> Fold<'source> for Rename {
+        fn fold_name(&mut self, name: Cow<'source, str>, span: Span) -> Expr<'source> {
+            let name = if name == "a" { Cow::Borrowed("z") } else { name };
+            Expr::Name { name, span }
+        }
+    }
+
+    // `--x` written as two prefix minuses collapses to `x`
+    struct DoubleNegation;
+
+    impl<'source> Fold<'source> for DoubleNegation {
+        fn fold_prefix(&mut self, op: TokenType, right: Box<Expr<'source>>, span: Span) -> Expr<'source> {
+            let right = self.fold_boxed(right);
+            match *right {
+                Expr::Prefix {
+                    op: TokenType::Minus,
+                    right: inner,
+                    ..
+                } if op == TokenType::Minus => *inner,
+                right => Expr::Prefix {
+                    op,
+                    right: Box::new(right),
+                    span,
+                },
+            }
+        }
+    }
+
+    #[test]
+    fn fold_rebuilds_untouched_nodes() {
+        let mut parser = BantamParser::new("a = f(a, b: a) ? a! : g(...a)");
+        let expr = parser.parse_expression();
+        let expr = Rename.fold_expr(*expr);
+        assert_eq!(expr.to_string(), "(a = (f(z, b: z) ? (z!) : g(...z)))");
+    }
+
+    #[test]
+    fn fold_rewrites_in_place() {
+        let mut parser = BantamParser::new("- - a + - - - b * c");
+        let expr = parser.parse_expression();
+        let expr = DoubleNegation.fold_expr(*expr);
+        assert_eq!(expr.to_string(), "(a + ((-b) * c))");
+    }
+}